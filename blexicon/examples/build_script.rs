@@ -0,0 +1,33 @@
+//! A build script generating the types of the lexicons of a crate, with
+//! blexicon as a build dependency. Saved as the `build.rs` of the crate,
+//! it turns the lexicons in `lexicons/` into a `lexicons` module:
+//!
+//! ```text
+//! // src/lib.rs
+//! mod lexicons;
+//! pub use lexicons::app::bsky::feed::post::Post;
+//! ```
+//!
+//! Run as an example, it takes the lexicon and output directories as its
+//! arguments instead, e.g.
+//! `cargo run --example build_script -- tests/fixtures /tmp/lexicons`.
+
+use blexicon::codegen::{codegen_dir, CodegenOptions};
+use std::path::PathBuf;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let input = PathBuf::from(args.next().unwrap_or_else(|| "lexicons".to_string()));
+    let output = PathBuf::from(args.next().unwrap_or_else(|| "src/lexicons".to_string()));
+    println!("cargo:rerun-if-changed={}", input.display());
+
+    let opts = CodegenOptions {
+        // the helpers once for all the modules
+        common: true,
+        ..Default::default()
+    };
+    if let Err(e) = codegen_dir(&input, &output, &opts) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,2425 @@
+//! Generation of Rust code from the lexicon data model.
+//!
+//! ```
+//! use blexicon::codegen::{generate_module, CodegenOptions};
+//!
+//! let lex = blexicon::parse_lexicon(r#"{
+//!     "lexicon": 1,
+//!     "id": "com.example.thing",
+//!     "defs": { "main": { "type": "object", "properties": {
+//!         "createdAt": { "type": "string", "format": "datetime" }
+//!     } } }
+//! }"#).unwrap();
+//! let module = generate_module(&lex, &CodegenOptions::default());
+//! assert_eq!(module.items[0].name, "Thing");
+//! assert!(module.helpers.contains("Datetime"));
+//! assert!(module.warnings.is_empty());
+//! assert!(module.to_code().contains("pub struct Thing {"));
+//! ```
+//!
+//! A union is an enum with a variant per ref, which the generated code
+//! gets at without a `match`:
+//!
+//! ```
+//! # use blexicon::codegen::{generate_module, CodegenOptions};
+//! let lex = blexicon::parse_lexicon(r##"{
+//!     "lexicon": 1,
+//!     "id": "com.example.pet",
+//!     "defs": {
+//!         "main": { "type": "union", "refs": ["#cat", "#dog"] },
+//!         "cat": { "type": "object", "properties": {} },
+//!         "dog": { "type": "object", "properties": {} }
+//!     }
+//! }"##).unwrap();
+//! let code = generate_module(&lex, &CodegenOptions::default()).to_code();
+//! // `pet.as_cat()` borrows the cat of a `Pet`, if it is one
+//! assert!(code.contains("pub fn as_cat(&self) -> Option<&cat> {"));
+//! // `pet.into_dog()` takes the dog out
+//! assert!(code.contains("pub fn into_dog(self) -> Option<dog> {"));
+//! // `Pet::from(cat)` makes the variant
+//! assert!(code.contains("impl From<cat> for Pet {"));
+//! ```
+use crate::*;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+/// What to generate, the same for all the lexicon files of a run.
+#[derive(Debug, Clone, Default)]
+pub struct CodegenOptions {
+    /// Extra traits to derive on all the generated types
+    pub derives: Vec<String>,
+    /// Whether each lexicon file goes into its own module, see `ModuleTree`
+    pub modules: bool,
+    /// Whether to generate a function calling each query and procedure
+    pub client: bool,
+    /// Whether structs get an `impl Default` with the schema defaults
+    pub default_impl: bool,
+    /// Whether the helpers go into a `common` module shared by all the
+    /// modules, rather than into each of them
+    pub common: bool,
+    /// Whether `datetime` strings become chrono timestamps
+    pub chrono: bool,
+    /// Whether integers that can't be negative are `u64`
+    pub unsigned: bool,
+    /// Whether struct fields are snake_case rather than named as in the lexicon
+    pub snake_case_fields: bool,
+    /// Whether structs are `#[serde(rename_all = "camelCase")]`, with snake_case fields
+    pub camel_case: bool,
+    /// The edition of the crate the code goes into
+    pub edition: Edition,
+    /// The naming conventions of the types
+    pub style: Style,
+    /// Whether object structs keep the `$type` they are tagged with
+    pub type_field: bool,
+    /// Whether strings that must not be empty are `NonEmptyString`, and the
+    /// `FromStr` of the format newtypes checks the format
+    pub strict_newtypes: bool,
+    /// Whether structs get a builder, with a setter per field and a
+    /// `build()` filling in the defaults and validating the result
+    pub builders: bool,
+    /// Whether the code goes into a `#![no_std]` crate with `alloc`: paths
+    /// are in `core`, and the alloc types are imported, see [`prelude`]
+    pub no_std: bool,
+    /// Whether to print the Rust type chosen for each property to stderr
+    pub trace: bool,
+}
+
+/// State carried across all the defs of a single run.
+#[derive(Debug, Default)]
+pub struct CodegenContext {
+    pub(crate) opts: CodegenOptions,
+    /// Helper types (e.g. format newtypes) that were already emitted
+    pub(crate) emitted_helpers: HashSet<String>,
+    /// NSID of the lexicon file being generated
+    pub(crate) nsid: String,
+    /// Name of the def being generated, as in the lexicon
+    pub(crate) def: String,
+    /// All the lexicon files of this run, by NSID, for resolving refs
+    pub(crate) lexicons: LexiconResolver,
+    /// The code of the helpers needed so far, by name, with `common`
+    pub(crate) common_helpers: BTreeMap<String, String>,
+    /// The helpers needed by the lexicon file being generated
+    pub(crate) helpers: BTreeSet<String>,
+    /// Problems of the lexicon file being generated, such as unresolved refs
+    pub(crate) warnings: Vec<Warning>,
+    /// Whether the type being generated is held in a Vec, which already
+    /// breaks any cycle of refs through it
+    pub(crate) indirect: bool,
+    /// Without modules, the defs whose names clash, see `flat_type_name`
+    pub(crate) clashes: HashSet<(String, String)>,
+}
+
+impl CodegenContext {
+    /// A context for generating any of the lexicons, which refs can point to.
+    pub fn new(opts: CodegenOptions, lexicons: LexiconResolver) -> Self {
+        let clashes = if opts.modules { HashSet::new() } else { flat_clashes(&lexicons, opts.style) };
+        CodegenContext {
+            opts,
+            lexicons,
+            clashes,
+            ..Default::default()
+        }
+    }
+
+    /// Forget about the helpers emitted so far, for code going into a
+    /// scope of its own, such as a module or a separate file.
+    pub fn new_scope(&mut self) {
+        self.emitted_helpers.clear();
+    }
+
+    /// Generate the code for all the defs of one lexicon file. Unless they
+    /// go into a `common` module, the helpers needed are part of the items,
+    /// the first time they are needed in the scope.
+    pub fn generate_module(&mut self, lex: &LexiconFile) -> GeneratedModule {
+        self.nsid = lex.id.clone();
+        let mut items = vec![];
+        for (name, def) in &lex.defs {
+            self.def = name.clone();
+            let type_name = self.type_name(&lex.id, name, Some(def));
+            let mut code = String::new();
+            for item in codegen_one_def(self, &type_name, def) {
+                code.push_str(&item);
+                code.push('\n');
+            }
+            items.push(GeneratedItem {
+                def: name.clone(),
+                name: type_name,
+                code: core_paths(&add_derives(&code, &self.opts.derives), self.opts.no_std),
+            });
+        }
+        GeneratedModule {
+            nsid: lex.id.clone(),
+            items,
+            helpers: std::mem::take(&mut self.helpers),
+            warnings: std::mem::take(&mut self.warnings),
+        }
+    }
+
+    /// The Rust type name of the def `defname` of the lexicon `nsid`, the
+    /// same for its declaration and the refs to it.
+    fn type_name(&self, nsid: &str, defname: &str, def: Option<&LexiconData>) -> String {
+        flat_type_name(self.opts.style, &self.clashes, nsid, defname, def)
+    }
+
+    /// Record a warning about the def being generated, once.
+    fn warn(&mut self, warning: Warning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// The full ref of the def being generated.
+    fn def_ref(&self) -> String {
+        format!("{}#{}", self.nsid, self.def)
+    }
+}
+
+/// The code generated for one lexicon file.
+#[derive(Debug, Clone)]
+pub struct GeneratedModule {
+    /// NSID of the lexicon file
+    pub nsid: String,
+    /// The code of each def, in the order of the file
+    pub items: Vec<GeneratedItem>,
+    /// The names of the helper types and functions the code needs, such as
+    /// `Datetime` or `ValidationError`
+    pub helpers: BTreeSet<String>,
+    /// Gaps in the code: refs to defs that are not among the lexicons,
+    /// which make it not compile as is, and defs with no code generated
+    pub warnings: Vec<Warning>,
+}
+
+/// A gap in the code generated for a def, which is named by its full ref,
+/// e.g. `app.bsky.feed.post#replyRef`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Warning {
+    /// A ref to a def that is not among the lexicons, `missing` being the
+    /// NSID of the lexicon, or the full ref of the def if the lexicon is
+    /// there
+    UnresolvedRef { def: String, reference: String, missing: String },
+    /// A def of a type there is no code for, but a comment, such as `array`
+    NotGenerated { def: String, kind: String },
+    /// The const of a string property is not among its enum or known
+    /// values, `reason` saying so. It is left out for an enum, which has
+    /// no variant for it
+    InvalidConst { def: String, property: String, value: String, reason: String },
+    /// The default of a string property is not a value it can have, which
+    /// `reason` tells, and is left out
+    InvalidDefault { def: String, property: String, value: String, reason: String },
+}
+
+impl Warning {
+    /// The full ref of the def the warning is about.
+    pub fn def(&self) -> &str {
+        match self {
+            Warning::UnresolvedRef { def, .. }
+            | Warning::NotGenerated { def, .. }
+            | Warning::InvalidConst { def, .. }
+            | Warning::InvalidDefault { def, .. } => def,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::UnresolvedRef { def, reference, missing } => {
+                write!(f, "{}: unresolved ref {}, missing {}", def, reference, missing)
+            }
+            Warning::NotGenerated { def, kind } => write!(f, "{}: no code for a def of type {}", def, kind),
+            Warning::InvalidConst { def, property, value, reason } => {
+                write!(f, "{}: the const {:?} of {} {}", def, value, property, reason)
+            }
+            Warning::InvalidDefault { def, property, value, reason } => {
+                write!(f, "{}: the default {:?} of {} {}", def, value, property, reason)
+            }
+        }
+    }
+}
+
+/// The code generated for one def.
+#[derive(Debug, Clone)]
+pub struct GeneratedItem {
+    /// Name of the def in the lexicon, e.g. `main`
+    pub def: String,
+    /// Name of its Rust type, e.g. `Post`
+    pub name: String,
+    /// The Rust types the def maps to, with their impls
+    pub code: String,
+}
+
+impl GeneratedModule {
+    /// All the code, without imports or documentation of the module.
+    pub fn to_code(&self) -> String {
+        self.items.iter().map(|item| item.code.as_str()).collect()
+    }
+}
+
+/// Generate the code of a lexicon file on its own: refs to other lexicon
+/// files can't be checked, and are all in the warnings.
+pub fn generate_module(lex: &LexiconFile, opts: &CodegenOptions) -> GeneratedModule {
+    let mut lexicons = LexiconResolver::new();
+    lexicons.insert(lex.clone());
+    CodegenContext::new(opts.clone(), lexicons).generate_module(lex)
+}
+
+/// A Rust edition the generated code can be compiled with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ArgEnum, serde::Serialize, serde::Deserialize)]
+pub enum Edition {
+    #[clap(name = "2018")]
+    #[serde(rename = "2018")]
+    E2018,
+    #[default]
+    #[clap(name = "2021")]
+    #[serde(rename = "2021")]
+    E2021,
+}
+
+impl Edition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Edition::E2018 => "2018",
+            Edition::E2021 => "2021",
+        }
+    }
+
+    /// The path of `TryFrom`, which is only in the prelude since 2021
+    fn try_from(self) -> &'static str {
+        match self {
+            Edition::E2018 => "std::convert::TryFrom",
+            Edition::E2021 => "TryFrom",
+        }
+    }
+}
+
+/// The naming conventions of the generated types.
+///
+/// With `atrium`, they are those of the atrium-api crate, in modules
+/// following the NSIDs as with `--modules`, e.g. `app::bsky::feed::post`:
+///
+/// - the `main` def of a record is `Record`, any other `main` def `Main`
+/// - the other defs are PascalCase, e.g. `ReplyRef` for `#replyRef`
+/// - the types of an endpoint, as for `app.bsky.feed.getTimeline`, are
+///   `Parameters`, `Input`, `Output` and `Error`, with `Message` for a
+///   subscription, next to an `NSID` constant
+/// - a union field of an object is `<Object><Field>Refs`, e.g.
+///   `RecordEmbedRefs`, and a union in an array `<Object><Field>Item`
+/// - a def whose PascalCase name is taken by a helper type or one of the
+///   names above, such as `#handle` of `com.atproto.sync.subscribeRepos`
+///   and the `Handle` string newtype, keeps its name in the lexicon
+///
+/// Unlike atrium, a record is not wrapped in an `Object`, so `Record` is
+/// the struct with the fields, and unions and tokens are generated as usual.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ArgEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    /// `main` is named after the last NSID segment, e.g. `Post` for
+    /// `app.bsky.feed.post`, the other defs as in the lexicon, and the
+    /// types of an endpoint after it, e.g. `GetTimelineParams`
+    #[default]
+    Blexicon,
+    /// The names of the atrium-api crate, in its module layout
+    Atrium,
+}
+
+/// The Rust type name of a def, `def` being None for a ref that can't be
+/// resolved, see `Style`.
+fn def_type_name(style: Style, nsid: &str, defname: &str, def: Option<&LexiconData>) -> String {
+    match (style, defname) {
+        (Style::Blexicon, "main") => capitalize(nsid.rsplit('.').next().unwrap_or(nsid)),
+        (Style::Blexicon, _) => defname.to_string(),
+        (Style::Atrium, "main") if matches!(def.map(|def| &def.data), Some(LexiconDataType::Record(_))) => {
+            "Record".to_string()
+        },
+        (Style::Atrium, "main") => "Main".to_string(),
+        (Style::Atrium, _) => {
+            let name = capitalize(defname);
+            if ATRIUM_TYPES.contains(&name.as_str()) || HELPER_TYPES.contains(&name.as_str()) {
+                defname.to_string()
+            } else {
+                name
+            }
+        },
+    }
+}
+
+/// The types of the atrium style, which defs can't be named after
+const ATRIUM_TYPES: &[&str] = &["Record", "Main", "Parameters", "Input", "Output", "Error", "Message"];
+
+/// The helper types, which defs can't be named after where they share a
+/// scope with them
+const HELPER_TYPES: &[&str] = &[
+    // the format newtypes, see format_newtype_name
+    "Did", "Handle", "AtIdentifier", "AtUri", "Datetime", "Cid", "Nsid", "Uri", "Language", "Tid", "RecordKey",
+    "Blob", "Bytes", "CidLink", NON_EMPTY_STRING, "EmptyString", "InvalidFormat", "ValidationError", "Constraint",
+    "XrpcClient", "XrpcError",
+];
+
+/// The Rust type name of a def in a flat output, where the types of all
+/// the lexicons share a scope: that of `def_type_name`, unless `clashes`
+/// holds the def, which then gets the NSID as a prefix, as in
+/// `ComExampleGetObjectThing` for `com.example.getObject#thing`, or
+/// `TestCid` for the `main` def of `test.cid`.
+fn flat_type_name(style: Style, clashes: &HashSet<(String, String)>, nsid: &str, defname: &str, def: Option<&LexiconData>) -> String {
+    let name = def_type_name(style, nsid, defname, def);
+    if !clashes.contains(&(nsid.to_string(), defname.to_string())) {
+        return name;
+    }
+    let prefix: String = nsid.split('.').map(capitalize).collect();
+    match (style, defname) {
+        (Style::Blexicon, "main") => prefix,
+        _ => prefix + &capitalize(&name),
+    }
+}
+
+/// The defs of the lexicons whose type names, in a flat output, would be
+/// those of defs of other lexicons or of helper types, by NSID and name.
+fn flat_clashes(lexicons: &LexiconResolver, style: Style) -> HashSet<(String, String)> {
+    let mut by_name: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for lex in lexicons.lexicons() {
+        for (name, def) in &lex.defs {
+            let type_name = def_type_name(style, &lex.id, name, Some(def));
+            by_name.entry(type_name).or_default().push((lex.id.clone(), name.clone()));
+        }
+    }
+    by_name
+        .into_iter()
+        .filter(|(name, defs)| defs.len() > 1 || HELPER_TYPES.contains(&name.as_str()))
+        .flat_map(|(_, defs)| defs)
+        .collect()
+}
+
+/// The name of one of the types of the endpoint `defname`, its `role`
+/// being `Params`, `Input`, `Output`, `Error` or `Message`.
+fn endpoint_type_name(ctx: &CodegenContext, defname: &str, role: &str) -> String {
+    let role = match (ctx.opts.style, role) {
+        (Style::Atrium, "Params") => "Parameters",
+        _ => role,
+    };
+    if ctx.opts.style == Style::Atrium && ctx.def == "main" {
+        role.to_string()
+    } else {
+        format!("{}{}", defname, role)
+    }
+}
+
+/// The Rust type name a ref points to. Refs to defs that are not part of
+/// this run get a warning, as the generated code will not compile as is.
+/// With a module per file, refs to other files get the path to their
+/// module, relative to the current one.
+fn ref_type_name(ctx: &mut CodegenContext, reference: &str) -> String {
+    if let Err(e) = ctx.lexicons.try_resolve(reference, &ctx.nsid) {
+        let missing = match e {
+            ResolveError::MissingDef { nsid, name, .. } => format!("{}#{}", nsid, name),
+            ResolveError::MissingLexicon { nsid, .. } => nsid,
+            e => e.to_string(),
+        };
+        let def = ctx.def_ref();
+        ctx.warn(Warning::UnresolvedRef { def, reference: reference.to_string(), missing });
+    }
+    let (nsid, fragment) = resolve::split_ref(reference, &ctx.nsid);
+    let def = ctx.lexicons.resolve(reference, &ctx.nsid);
+    let name = ctx.type_name(nsid, fragment, def);
+    if !ctx.opts.modules || nsid == ctx.nsid {
+        return name;
+    }
+    let mut path = "super::".repeat(ctx.nsid.split('.').count());
+    for segment in nsid.split('.') {
+        path.push_str(&snake_case(segment));
+        path.push_str("::");
+    }
+    path + &name
+}
+
+/// The refs a def holds a value of, as opposed to a Vec of: those of its
+/// fields and union variants, down nested objects but not arrays.
+fn direct_refs(def: &LexiconData) -> Vec<&str> {
+    match &def.data {
+        LexiconDataType::Ref(r) => vec![r.reference.as_str()],
+        LexiconDataType::Union(u) => u.refs.iter().map(String::as_str).collect(),
+        LexiconDataType::Object(o) => o.properties.values().flat_map(direct_refs).collect(),
+        LexiconDataType::Record(r) => r.record.properties.values().flat_map(direct_refs).collect(),
+        _ => vec![],
+    }
+}
+
+/// Whether a value of the ref, held by the def being generated other than
+/// through a Vec, would hold a value of the def in turn, as for the parent
+/// of a thread. The type would then have an infinite size, so the ref is
+/// boxed: all of those on the cycle are, which is more than needed.
+fn is_recursive_ref(ctx: &CodegenContext, reference: &str) -> bool {
+    if ctx.indirect {
+        return false;
+    }
+    let (nsid, name) = resolve::split_ref(reference, &ctx.nsid);
+    let mut seen = HashSet::new();
+    let mut pending = vec![(nsid.to_string(), name.to_string())];
+    while let Some((nsid, name)) = pending.pop() {
+        if nsid == ctx.nsid && name == ctx.def {
+            return true;
+        }
+        if !seen.insert((nsid.clone(), name.clone())) {
+            continue;
+        }
+        let Some(def) = ctx.lexicons.get(&nsid).and_then(|lex| lex.defs.get(&name)) else {
+            continue;
+        };
+        for reference in direct_refs(def) {
+            let (ref_nsid, ref_name) = resolve::split_ref(reference, &nsid);
+            pending.push((ref_nsid.to_string(), ref_name.to_string()));
+        }
+    }
+    false
+}
+
+/// The `$type` value that identifies the target of a ref on the wire:
+/// local refs are expanded with the current NSID, and `#main` is implicit.
+fn ref_type_tag(nsid: &str, reference: &str) -> String {
+    let full = if reference.starts_with('#') {
+        format!("{}{}", nsid, reference)
+    } else {
+        reference.to_string()
+    };
+    full.strip_suffix("#main").map(str::to_string).unwrap_or(full)
+}
+
+/// Newtype name to use for a given string format, if there is one.
+fn format_newtype_name(format: &str) -> Option<&'static str> {
+    let name = match format {
+        "did" => "Did",
+        "handle" => "Handle",
+        "at-identifier" => "AtIdentifier",
+        "at-uri" => "AtUri",
+        "datetime" => "Datetime",
+        "cid" => "Cid",
+        "nsid" => "Nsid",
+        "uri" => "Uri",
+        "language" => "Language",
+        "tid" => "Tid",
+        "record-key" => "RecordKey",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Turn an arbitrary string value into a CamelCase enum variant name.
+fn variant_name(value: &str) -> String {
+    let name: String = value
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .map(capitalize)
+        .collect();
+    if name.is_empty() {
+        "Empty".to_string()
+    } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("V{}", name)
+    } else {
+        name
+    }
+}
+
+/// Generate an enum over the string values. A closed set (`enum`) accepts only
+/// the listed values, an open set (`knownValues`) gets a catch-all variant so
+/// that unknown values still round-trip.
+fn codegen_string_enum(name: &str, values: &[String], open: bool) -> String {
+    let mut variants = String::new();
+    for (value, variant) in enum_variants(values) {
+        variants.push_str(&format!("    #[serde(rename = \"{}\")]\n    {},\n", value, variant));
+    }
+    if open {
+        // serde(other) only works on unit variants, an untagged one keeps the value
+        variants.push_str("    #[serde(untagged)]\n    Other(String),\n");
+    }
+    format!(
+        "#[derive(Debug, Clone, Serialize, Deserialize)]\npub enum {} {{\n{}}}\n\n",
+        name, variants
+    )
+}
+
+/// Pair each of the string values with a distinct enum variant name.
+fn enum_variants(values: &[String]) -> Vec<(&str, String)> {
+    let mut seen = HashSet::new();
+    values
+        .iter()
+        .map(|value| {
+            let mut variant = variant_name(value);
+            while !seen.insert(variant.clone()) {
+                variant.push('_');
+            }
+            (value.as_str(), variant)
+        })
+        .collect()
+}
+
+/// Why a string property can't have `value`, should it be so. Such a value
+/// has no Rust expression: a closed enum has no variant for it, and a datetime
+/// that is not RFC 3339, or an empty string with a minimum length, would panic
+/// when made a chrono datetime or a `NonEmptyString`.
+fn invalid_string_value(s: &StringType, value: &str) -> Option<&'static str> {
+    match &s.allowed_enum {
+        Some(values) if !values.iter().any(|v| v == value) => Some(NOT_AMONG_VALUES),
+        _ if s.format.as_deref() == Some("datetime") && chrono::DateTime::parse_from_rfc3339(value).is_err() => Some("is not an RFC 3339 datetime"),
+        _ if value.is_empty() && [s.minLength, s.minGraphemes].iter().any(|min| min.is_some_and(|min| min >= 1)) => Some("is empty, below its minimum length"),
+        _ => None,
+    }
+}
+
+/// The reason of a warning about a string value not among the allowed ones
+const NOT_AMONG_VALUES: &str = "is not among its values";
+
+/// Rust expression for the string `value`, as the type `string_type` picked.
+fn string_value(s: &StringType, rust_type: &str, value: &str) -> String {
+    if let Some(values) = s.allowed_enum.as_ref().or(s.knownValues.as_ref()) {
+        match enum_variants(values).into_iter().find(|(v, _)| *v == value) {
+            Some((_, variant)) => format!("{}::{}", rust_type, variant),
+            None => format!("{}::Other({:?}.to_string())", rust_type, value),
+        }
+    } else if rust_type == "String" {
+        format!("{:?}.to_string()", value)
+    } else if rust_type == CHRONO_DATETIME {
+        format!("chrono::DateTime::parse_from_rfc3339({:?}).unwrap().with_timezone(&chrono::Utc)", value)
+    } else if rust_type == NON_EMPTY_STRING {
+        format!("{}::new({:?}).unwrap()", rust_type, value)
+    } else {
+        format!("{}({:?}.to_string())", rust_type, value)
+    }
+}
+
+/// The type of `datetime` strings with `--chrono`
+pub(crate) const CHRONO_DATETIME: &str = "chrono::DateTime<chrono::Utc>";
+
+/// Serde functions for the chrono timestamps, used through `#[serde(with)]`.
+const DATETIME_RFC3339: &str = r#"/// RFC 3339 timestamps as chrono UTC times. Any offset is accepted, `Z` or
+/// numeric, with or without fractional seconds; they are written in UTC with
+/// a `Z`, keeping the fractional digits there are.
+pub mod datetime_rfc3339 {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};{alloc}
+
+    pub fn to_string(value: &DateTime<Utc>) -> String {
+        value.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    }
+
+    pub fn parse(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+        DateTime::parse_from_rfc3339(s).map(|t| t.with_timezone(&Utc))
+    }
+
+    pub fn serialize<S: Serializer>(value: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_string(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse(&s).map_err(serde::de::Error::custom)
+    }
+
+    /// The same for optional fields, which also need `#[serde(default)]`
+    pub mod option {
+        use chrono::{DateTime, Utc};
+        use serde::{Deserialize, Deserializer, Serializer};{alloc}
+
+        pub fn serialize<S: Serializer>(value: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => serializer.serialize_some(&super::to_string(value)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(s) => super::parse(&s).map(Some).map_err(serde::de::Error::custom),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+"#;
+
+/// Resolve the Rust type of a string. Strings restricted to a set of values
+/// become an enum called `name`, others get the newtype for their format,
+/// which is emitted the first time it is seen during this run. With
+/// `--chrono`, `datetime` strings are chrono timestamps instead.
+fn string_type(ctx: &mut CodegenContext, name: &str, s: &StringType, out: &mut Vec<String>) -> String {
+    if let Some(values) = &s.allowed_enum {
+        out.push(codegen_string_enum(name, values, false));
+        return name.to_string();
+    }
+    if let Some(values) = &s.knownValues {
+        out.push(codegen_string_enum(name, values, true));
+        return name.to_string();
+    }
+    if ctx.opts.chrono && s.format.as_deref() == Some("datetime") {
+        // a module of its own, out of the reach of the imports of the prelude
+        let alloc = if ctx.opts.no_std { " use alloc::string::String;" } else { "" };
+        emit_helper(ctx, "datetime_rfc3339", out, || DATETIME_RFC3339.replace("{alloc}", alloc));
+        return CHRONO_DATETIME.to_string();
+    }
+    let Some(name) = s.format.as_deref().and_then(format_newtype_name) else {
+        if ctx.opts.strict_newtypes && s.minLength.or(s.minGraphemes).is_some_and(|min| min >= 1) {
+            let try_from = ctx.opts.edition.try_from();
+            emit_helper(ctx, NON_EMPTY_STRING, out, || NON_EMPTY_STRING_CODE.replace("{try_from}", try_from));
+            return NON_EMPTY_STRING.to_string();
+        }
+        return "String".to_string();
+    };
+    let format = s.format.as_deref().unwrap_or_default();
+    let from_str = if ctx.opts.strict_newtypes {
+        emit_helper(ctx, "InvalidFormat", out, || INVALID_FORMAT.to_string());
+        format!(
+            "    type Err = InvalidFormat;\n\n    fn from_str(s: &str) -> Result<Self, Self::Err> {{\n        if {} {{\n            Ok({}(s.to_string()))\n        }} else {{\n            Err(InvalidFormat {{ format: \"{}\", value: s.to_string() }})\n        }}\n    }}\n",
+            format_check(format),
+            name,
+            format
+        )
+    } else {
+        format!(
+            "    type Err = std::convert::Infallible;\n\n    fn from_str(s: &str) -> Result<Self, Self::Err> {{\n        Ok({}(s.to_string()))\n    }}\n",
+            name
+        )
+    };
+    emit_helper(ctx, name, out, || {
+        format!(
+            "#[derive(Debug, Clone, Serialize, Deserialize)]\n#[serde(transparent)]\npub struct {name}(pub String);\n\nimpl std::fmt::Display for {name} {{\n    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{\n        f.write_str(&self.0)\n    }}\n}}\n\nimpl std::str::FromStr for {name} {{\n{from_str}}}\n\n",
+            name = name,
+            from_str = from_str
+        )
+    });
+    name.to_string()
+}
+
+/// A cheap check of the shape of a string `s` of the format, for `FromStr`
+/// with `--strict-newtypes`, rather than a full parse.
+fn format_check(format: &str) -> &'static str {
+    match format {
+        "did" => r#"s.starts_with("did:")"#,
+        "handle" => "s.contains('.')",
+        "at-identifier" => r#"s.starts_with("did:") || s.contains('.')"#,
+        "at-uri" => r#"s.starts_with("at://")"#,
+        "datetime" => "s.contains('T')",
+        "nsid" => "s.split('.').count() >= 3 && !s.split('.').any(str::is_empty)",
+        "uri" => "s.contains(':')",
+        "tid" => "s.len() == 13",
+        "record-key" => r#"!s.is_empty() && s.len() <= 512 && s != "." && s != "..""#,
+        // cid, language
+        _ => "!s.is_empty()",
+    }
+}
+
+/// The error of the `FromStr` of the format newtypes with `--strict-newtypes`
+const INVALID_FORMAT: &str = r#"/// A string that does not have the format of the newtype it is parsed into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidFormat {
+    /// The format in the lexicon, e.g. `did`
+    pub format: &'static str,
+    pub value: String,
+}
+
+impl std::fmt::Display for InvalidFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} is not a valid {}", self.value, self.format)
+    }
+}
+
+impl std::error::Error for InvalidFormat {}
+
+"#;
+
+/// The type of the strings with a minimum length with `--strict-newtypes`
+const NON_EMPTY_STRING: &str = "NonEmptyString";
+
+/// A string that is checked not to be empty when deserialized or created,
+/// the other constraints on its length being left to validate().
+const NON_EMPTY_STRING_CODE: &str = r#"/// A string that is not empty, for a lexicon `minLength` or `minGraphemes`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NonEmptyString(String);
+
+impl NonEmptyString {
+    /// The string, unless it is empty
+    pub fn new(s: impl Into<String>) -> Option<Self> {
+        let s = s.into();
+        if s.is_empty() {
+            None
+        } else {
+            Some(NonEmptyString(s))
+        }
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl std::ops::Deref for NonEmptyString {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.0
+    }
+}
+
+impl {try_from}<String> for NonEmptyString {
+    type Error = EmptyString;
+
+    fn try_from(s: String) -> Result<Self, EmptyString> {
+        NonEmptyString::new(s).ok_or(EmptyString)
+    }
+}
+
+impl From<NonEmptyString> for String {
+    fn from(s: NonEmptyString) -> String {
+        s.0
+    }
+}
+
+/// The error of making a `NonEmptyString` out of an empty string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmptyString;
+
+impl std::fmt::Display for EmptyString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the string is empty")
+    }
+}
+
+impl std::error::Error for EmptyString {}
+
+"#;
+
+/// Error type returned by the generated `validate()` methods.
+const VALIDATION_ERROR: &str = r#"/// The lexicon constraint that a value does not satisfy.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    Minimum(i64),
+    Maximum(i64),
+    Enum(&'static [i64]),
+    /// Length in UTF-8 bytes
+    MinLength(i64),
+    MaxLength(i64),
+    /// Length in grapheme clusters, as counted by unicode-segmentation
+    MinGraphemes(i64),
+    MaxGraphemes(i64),
+    /// The fixed value the field must have
+    Const(&'static str),
+    /// MIME types a blob may have, `*` matching any suffix
+    Accept(&'static [&'static str]),
+    MaxSize(i64),
+    /// A required field left unset, when building the value
+    Required,
+}
+
+/// A field of a generated type that violates its lexicon constraints.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub constraint: Constraint,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: violates {:?}", self.field, self.constraint)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+"#;
+
+/// Validation statements for a string `value` against its lexicon lengths.
+fn string_checks(propname: &str, s: &StringType, value: &str) -> String {
+    let mut checks = String::new();
+    let bytes = format!("{}.len() as i64", value);
+    let graphemes = format!(
+        "unicode_segmentation::UnicodeSegmentation::graphemes({}.as_str(), true).count() as i64",
+        value
+    );
+    if let Some(min) = s.minLength {
+        checks.push_str(&check(propname, &format!("{} >= {}", bytes, min), &format!("MinLength({})", min)));
+    }
+    if let Some(max) = s.maxLength {
+        checks.push_str(&check(propname, &format!("{} <= {}", bytes, max), &format!("MaxLength({})", max)));
+    }
+    if let Some(min) = s.minGraphemes {
+        checks.push_str(&check(propname, &format!("{} >= {}", graphemes, min), &format!("MinGraphemes({})", min)));
+    }
+    if let Some(max) = s.maxGraphemes {
+        checks.push_str(&check(propname, &format!("{} <= {}", graphemes, max), &format!("MaxGraphemes({})", max)));
+    }
+    checks
+}
+
+/// The canonical blob type, shared by all blob fields.
+const BLOB: &str = r#"/// Reference to a blob stored separately from the record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "$type", rename = "blob")]
+pub struct Blob {
+    pub r#ref: CidLink,
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    pub size: i64,
+}
+
+"#;
+
+/// Link to content by its CID.
+const CID_LINK: &str = r#"/// Link to content by its CID. Serialized as `{"$link": "..."}` like in
+/// DAG-JSON, and deserialized from that or from a plain CID string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CidLink(pub String);
+
+impl Serialize for CidLink {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("$link", &self.0)?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for CidLink {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Link {
+                #[serde(rename = "$link")]
+                link: String,
+            },
+            Plain(String),
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Link { link } => CidLink(link),
+            Repr::Plain(cid) => CidLink(cid),
+        })
+    }
+}
+
+"#;
+
+/// Resolve the type of a cid-link, emitting the helper when first seen.
+fn cid_link_type(ctx: &mut CodegenContext, out: &mut Vec<String>) -> String {
+    emit_helper(ctx, "CidLink", out, || CID_LINK.to_string());
+    "CidLink".to_string()
+}
+
+/// Raw bytes, in the DAG-JSON `$bytes` form.
+const BYTES: &str = r#"/// Raw bytes. Serialized as `{"$bytes": "<base64>"}` like in DAG-JSON, using
+/// the standard alphabet without padding; padding is accepted when reading.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytes(pub Vec<u8>);
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl Bytes {
+    fn to_base64(&self) -> String {
+        let mut out = String::new();
+        for chunk in self.0.chunks(3) {
+            let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+            for i in 0..=chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        out
+    }
+
+    fn from_base64(s: &str) -> Option<Bytes> {
+        let mut out = vec![];
+        let (mut n, mut bits) = (0u32, 0);
+        for c in s.trim_end_matches('=').bytes() {
+            let v = BASE64_ALPHABET.iter().position(|a| *a == c)? as u32;
+            n = n << 6 | v;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((n >> bits) as u8);
+            }
+        }
+        Some(Bytes(out))
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("$bytes", &self.to_base64())?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Repr {
+            #[serde(rename = "$bytes")]
+            bytes: String,
+        }
+        let repr = Repr::deserialize(deserializer)?;
+        Bytes::from_base64(&repr.bytes).ok_or_else(|| serde::de::Error::custom("invalid base64 in $bytes"))
+    }
+}
+
+"#;
+
+/// Resolve the type of bytes, emitting the helper when first seen.
+fn bytes_type(ctx: &mut CodegenContext, out: &mut Vec<String>) -> String {
+    emit_helper(ctx, "Bytes", out, || BYTES.to_string());
+    "Bytes".to_string()
+}
+
+/// Validation statements for bytes `v` against their lexicon lengths.
+fn bytes_checks(propname: &str, b: &BytesType) -> String {
+    let mut checks = String::new();
+    if let Some(min) = b.minLength {
+        checks.push_str(&check(propname, &format!("v.0.len() >= {}", min), &format!("MinLength({})", min)));
+    }
+    if let Some(max) = b.maxLength {
+        checks.push_str(&check(propname, &format!("v.0.len() <= {}", max), &format!("MaxLength({})", max)));
+    }
+    checks
+}
+
+/// Resolve the type of a blob, emitting the blob helpers when first seen.
+fn blob_type(ctx: &mut CodegenContext, out: &mut Vec<String>) -> String {
+    cid_link_type(ctx, out);
+    emit_helper(ctx, "Blob", out, || BLOB.to_string());
+    "Blob".to_string()
+}
+
+/// Validation statements for a blob `v` against its size and accepted types.
+fn blob_checks(propname: &str, b: &BlobType) -> String {
+    let mut checks = String::new();
+    if !b.accept.is_empty() && !b.accept.iter().any(|a| a == "*/*") {
+        let accept = format!("&{:?}", b.accept);
+        let cond = format!(
+            "{}.iter().any(|a| match a.strip_suffix('*') {{ Some(prefix) => v.mime_type.starts_with(prefix), None => *a == v.mime_type }})",
+            accept
+        );
+        checks.push_str(&check(propname, &cond, &format!("Accept({})", accept)));
+    }
+    if let Some(max) = b.maxSize {
+        checks.push_str(&check(propname, &format!("v.size <= {}", max), &format!("MaxSize({})", max)));
+    }
+    checks
+}
+
+/// Deserializer of optional `null` fields, see `codegen_one_def`.
+const PRESENT_NULL: &str = r#"/// A `null` field that is there, so that only an absent one is a `None`.
+pub fn present_null<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<()>, D::Error> {
+    <()>::deserialize(deserializer).map(Some)
+}
+
+"#;
+
+/// Push the code of a helper type, unless it was already emitted in this scope.
+/// With a `common` module, the helper is only registered for it instead.
+fn emit_helper(ctx: &mut CodegenContext, name: &str, out: &mut Vec<String>, code: impl FnOnce() -> String) {
+    ctx.helpers.insert(name.to_string());
+    if ctx.opts.common {
+        ctx.common_helpers.entry(name.to_string()).or_insert_with(code);
+    } else if ctx.emitted_helpers.insert(name.to_string()) {
+        out.push(code());
+    }
+}
+
+/// A validation statement failing with `constraint` unless `cond` holds for `v`.
+fn check(propname: &str, cond: &str, constraint: &str) -> String {
+    format!(
+        "            if !({}) {{\n                return Err(ValidationError {{ field: \"{}\", constraint: Constraint::{} }});\n            }}\n",
+        cond, propname, constraint
+    )
+}
+
+/// The Rust type of an integer: `i64`, or `u64` with `--unsigned` when its
+/// minimum is not negative.
+fn integer_type(ctx: &CodegenContext, i: &IntegerType) -> &'static str {
+    if ctx.opts.unsigned && i.minimum.is_some_and(|min| min >= 0) {
+        "u64"
+    } else {
+        "i64"
+    }
+}
+
+/// Validation statements for an integer `v`, of type `rust_type`, against
+/// its lexicon bounds.
+fn integer_checks(propname: &str, i: &IntegerType, rust_type: &str) -> String {
+    let mut checks = String::new();
+    // a u64 is never below 0, and saying so would be warned about
+    if let Some(min) = i.minimum.filter(|&min| rust_type != "u64" || min > 0) {
+        checks.push_str(&check(propname, &format!("*v >= {}", min), &format!("Minimum({})", min)));
+    }
+    if let Some(max) = i.maximum {
+        checks.push_str(&check(propname, &format!("*v <= {}", max), &format!("Maximum({})", max)));
+    }
+    if let Some(values) = &i.allowed_enum {
+        let values = format!("{:?}", values);
+        checks.push_str(&check(propname, &format!("{}.contains(v)", values), &format!("Enum(&{})", values)));
+    }
+    checks
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+/// Keywords that can be used as raw identifiers, e.g. `r#type`.
+const RAW_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+/// Keywords that are not allowed even as raw identifiers.
+const NON_RAW_KEYWORDS: &[&str] = &["self", "Self", "super", "crate", "_"];
+
+/// Convert a property name into a valid Rust field name. Keywords become raw
+/// identifiers, except those that can't, which get an underscore prefix like
+/// names starting with a digit do.
+pub fn field_name(propname: &str) -> String {
+    let name: String = propname
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if RAW_KEYWORDS.contains(&name.as_str()) {
+        format!("r#{}", name)
+    } else if NON_RAW_KEYWORDS.contains(&name.as_str()) || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// Resolve the Rust type of a property or array item. Inline objects and
+/// unions get a type of their own, called `name`, with the items of an
+/// array being `nameItem`.
+fn field_type(ctx: &mut CodegenContext, name: &str, def: &LexiconData, out: &mut Vec<String>) -> String {
+    match &def.data {
+        LexiconDataType::String(s) => string_type(ctx, name, s, out),
+        LexiconDataType::Integer(i) => integer_type(ctx, i).to_string(),
+        LexiconDataType::Boolean(_) => "bool".to_string(),
+        LexiconDataType::Null => "()".to_string(),
+        LexiconDataType::Array(arr) => {
+            let indirect = std::mem::replace(&mut ctx.indirect, true);
+            let item_type = field_type(ctx, &format!("{}Item", name), &arr.items, out);
+            ctx.indirect = indirect;
+            format!("Vec<{}>", item_type)
+        },
+        LexiconDataType::Ref(r) if is_recursive_ref(ctx, &r.reference) => {
+            format!("Box<{}>", ref_type_name(ctx, &r.reference))
+        },
+        LexiconDataType::Ref(r) => ref_type_name(ctx, &r.reference),
+        LexiconDataType::CidLink => cid_link_type(ctx, out),
+        LexiconDataType::Bytes(_) => bytes_type(ctx, out),
+        LexiconDataType::Blob(_) => blob_type(ctx, out),
+        // arbitrary data, usually an object with a `$type`
+        LexiconDataType::Unknown => "serde_json::Value".to_string(),
+        LexiconDataType::Object(inner_obj) => {
+            // For nested objects, we'll create a new type name based on the parent and property name
+            codegen_object(ctx, name, inner_obj, ctx.opts.type_field, out);
+            name.to_string()
+        },
+        LexiconDataType::Union(u) => {
+            out.push(codegen_union(ctx, name, u));
+            name.to_string()
+        },
+        _ => "String".to_string() // Default fallback
+    }
+}
+
+/// Whether the lexicon gives the property a value when absent
+fn has_schema_default(def: &LexiconData) -> bool {
+    match &def.data {
+        LexiconDataType::String(s) => {
+            let valid = |value: &Option<String>| value.as_deref().is_some_and(|v| invalid_string_value(s, v).is_none());
+            valid(&s.default) || valid(&s.constant)
+        },
+        LexiconDataType::Integer(i) => i.default.is_some() || i.constant.is_some(),
+        LexiconDataType::Boolean(b) => b.default.is_some() || b.constant.is_some(),
+        _ => false,
+    }
+}
+
+/// The "empty" value of a type, for the fields that have no schema default,
+/// if there is one: enums and unions have no obvious value to pick. `nsid`
+/// is the lexicon of the def, to resolve its refs, and `seen` the refs
+/// already followed, as a cycle of required fields has no value either.
+fn type_default(ctx: &CodegenContext, nsid: &str, def: &LexiconData, rust_type: &str, seen: &mut HashSet<String>) -> Option<String> {
+    let value = match &def.data {
+        LexiconDataType::String(s) if s.allowed_enum.is_some() || s.knownValues.is_some() => return None,
+        LexiconDataType::Integer(_) => "0".to_string(),
+        LexiconDataType::Boolean(_) => "false".to_string(),
+        LexiconDataType::Null => "()".to_string(),
+        LexiconDataType::Array(_) => "Vec::new()".to_string(),
+        LexiconDataType::Unknown => "serde_json::Value::Null".to_string(),
+        LexiconDataType::CidLink => "CidLink(String::new())".to_string(),
+        LexiconDataType::Bytes(_) => "Bytes(Vec::new())".to_string(),
+        LexiconDataType::Blob(_) => {
+            "Blob { r#ref: CidLink(String::new()), mime_type: String::new(), size: 0 }".to_string()
+        },
+        LexiconDataType::Object(o) => {
+            if !object_has_default(ctx, nsid, o, seen) {
+                return None;
+            }
+            "Default::default()".to_string()
+        },
+        LexiconDataType::Ref(r) => {
+            let (ref_nsid, _) = resolve::split_ref(&r.reference, nsid);
+            let object = match ctx.lexicons.resolve(&r.reference, nsid).map(|d| &d.data) {
+                Some(LexiconDataType::Object(o)) => o,
+                Some(LexiconDataType::Record(r)) => &r.record,
+                _ => return None,
+            };
+            if !seen.insert(ref_type_tag(nsid, &r.reference)) {
+                return None;
+            }
+            if !object_has_default(ctx, ref_nsid, object, seen) {
+                return None;
+            }
+            "Default::default()".to_string()
+        },
+        LexiconDataType::Union(_) => return None,
+        // strings, possibly with a newtype for their format
+        _ if rust_type == "String" => "String::new()".to_string(),
+        _ if rust_type == NON_EMPTY_STRING => return None,
+        _ if rust_type == CHRONO_DATETIME => "chrono::DateTime::<chrono::Utc>::default()".to_string(),
+        _ => format!("{}(String::new())", rust_type),
+    };
+    Some(value)
+}
+
+/// Whether all the fields of the object have some default value, so that
+/// its struct gets an `impl Default`.
+fn object_has_default(ctx: &CodegenContext, nsid: &str, o: &ObjectType, seen: &mut HashSet<String>) -> bool {
+    o.properties.iter().all(|(propname, propdef)| {
+        let needs_value = o.required.contains(propname) && !o.nullable.contains(propname) && !has_schema_default(propdef);
+        // the Rust type only matters for format newtypes, and any non-String will do
+        !needs_value || type_default(ctx, nsid, propdef, "", seen).is_some()
+    })
+}
+
+/// What became of an object property in the generated struct.
+#[derive(Debug, Clone)]
+struct GeneratedField {
+    /// Name of the property in the lexicon
+    propname: String,
+    /// Name of the struct field
+    field: String,
+    /// Whether the field is wrapped in an Option
+    optional: bool,
+    /// Whether the property is an array
+    array: bool,
+}
+
+/// The name of the struct field of a property.
+fn struct_field_name(ctx: &CodegenContext, propname: &str) -> String {
+    if ctx.opts.snake_case_fields || ctx.opts.camel_case {
+        field_name(&snake_case(propname))
+    } else {
+        field_name(propname)
+    }
+}
+
+/// Generate a struct for the object, followed by the structs for any
+/// inline objects nested within it, to arbitrary depth. With `type_field`,
+/// the struct has an extra field for the `$type` of the object, unless the
+/// lexicon declares it.
+fn codegen_object(
+    ctx: &mut CodegenContext,
+    defname: &str,
+    o: &ObjectType,
+    type_field: bool,
+    out: &mut Vec<String>,
+) -> Vec<GeneratedField> {
+    let mut nested = vec![];
+    let mut fields = vec![];
+    let mut fields_str = String::new();
+    let mut validations = String::new();
+    // the expressions of the fields in an `impl Default`, or the first field without one
+    let mut defaults: Result<String, String> = Ok(String::new());
+    // the fields of the builder, with the type of their setter and how build() fills them in
+    let mut builder_fields: Vec<(String, String, String)> = vec![];
+    emit_helper(ctx, "ValidationError", &mut nested, || VALIDATION_ERROR.to_string());
+    if type_field && !o.properties.contains_key("$type") {
+        // `_type` being what a `$type` property is called, it is free
+        let taken = o.properties.keys().any(|propname| struct_field_name(ctx, propname) == "r#type");
+        let field = if taken { "_type" } else { "r#type" };
+        fields_str.push_str(&format!(
+            "    /// The `$type` the object is tagged with, if any, kept for re-encoding it as it was
+    #[serde(rename = \"$type\", default, skip_serializing_if = \"Option::is_none\")]
+    pub {}: Option<String>,
+",
+            field
+        ));
+        if let Ok(inits) = &mut defaults {
+            inits.push_str(&format!("            {}: None,
+", field));
+        }
+        builder_fields.push((field.to_string(), "String".to_string(), format!("self.{}", field)));
+    }
+    for (propname, propdef) in &o.properties {
+        let is_required = o.required.contains(propname);
+        // a null is the one value of a `null` field, nullable or not
+        let is_nullable = o.nullable.contains(propname) && !matches!(propdef.data, LexiconDataType::Null);
+        let nested_name = match (ctx.opts.style, &propdef.data) {
+            (Style::Atrium, LexiconDataType::Union(_)) => format!("{}{}Refs", defname, capitalize(propname)),
+            _ => format!("{}{}", defname, capitalize(propname)),
+        };
+        // Determine the Rust type based on the property definition
+        let rust_type = field_type(ctx, &nested_name, propdef, &mut nested);
+
+        // Schema defaults of scalar fields, as Rust expressions
+        let default_value = match &propdef.data {
+            LexiconDataType::String(s) => match &s.default {
+                Some(d) => match invalid_string_value(s, d) {
+                    Some(reason) => {
+                        let def = ctx.def_ref();
+                        ctx.warn(Warning::InvalidDefault { def, property: propname.clone(), value: d.clone(), reason: reason.to_string() });
+                        None
+                    },
+                    None => Some(string_value(s, &rust_type, d)),
+                },
+                None => None,
+            },
+            LexiconDataType::Integer(i) => i.default.map(|d| d.to_string()),
+            LexiconDataType::Boolean(b) => b.default.map(|d| d.to_string()),
+            _ => None,
+        };
+        // Constant fields, as the value expression, the check against it, the
+        // literal and the value as text, for `Constraint::Const`
+        let const_value = match &propdef.data {
+            LexiconDataType::String(s) => {
+                let invalid = s.constant.as_deref().and_then(|c| invalid_string_value(s, c));
+                match (&s.constant, invalid, s.allowed_enum.as_ref().or(s.knownValues.as_ref())) {
+                    (Some(c), Some(reason), _) => {
+                        let def = ctx.def_ref();
+                        ctx.warn(Warning::InvalidConst { def, property: propname.clone(), value: c.clone(), reason: reason.to_string() });
+                        None
+                    }
+                    (Some(c), None, Some(values)) if !values.contains(c) => {
+                        let def = ctx.def_ref();
+                        ctx.warn(Warning::InvalidConst { def, property: propname.clone(), value: c.clone(), reason: NOT_AMONG_VALUES.to_string() });
+                        // unlike an enum, known values have an `Other` for it
+                        let cond = format!("matches!(v, {}::Other(other) if other == {:?})", rust_type, c);
+                        Some((string_value(s, &rust_type, c), Some(cond), format!("{:?}", c), c.clone()))
+                    }
+                    (Some(c), None, values) => {
+                        let cond = match (rust_type.as_str(), values) {
+                            (_, Some(_)) => format!("matches!(v, {})", string_value(s, &rust_type, c)),
+                            ("String", _) => format!("*v == {:?}", c),
+                            (CHRONO_DATETIME, _) => format!("datetime_rfc3339::to_string(v) == {:?}", c),
+                            (NON_EMPTY_STRING, _) => format!("v.as_str() == {:?}", c),
+                            _ => format!("v.0 == {:?}", c),
+                        };
+                        Some((string_value(s, &rust_type, c), Some(cond), format!("{:?}", c), c.clone()))
+                    }
+                    (None, ..) => None,
+                }
+            },
+            LexiconDataType::Integer(i) => i.constant.map(|c| (c.to_string(), Some(format!("*v == {}", c)), c.to_string(), c.to_string())),
+            LexiconDataType::Boolean(b) => b.constant.map(|c| (c.to_string(), Some(format!("*v == {}", c)), c.to_string(), c.to_string())),
+            _ => None,
+        };
+        // a const always has its value, which takes the place of any default
+        let default_value = const_value.as_ref().map(|(value, _, _, _)| value.clone()).or(default_value);
+        // a default fills in an absent field, unless an explicit null is allowed
+        let default_value = default_value.filter(|_| !is_nullable);
+
+        // Build the type with Option wrapper if needed
+        let is_option = (!is_required && default_value.is_none()) || is_nullable;
+        let is_chrono = rust_type == CHRONO_DATETIME;
+        let setter_type = rust_type.clone();
+        let final_type = if is_option {
+            format!("Option<{}>", rust_type)
+        } else {
+            rust_type
+        };
+        if ctx.opts.trace {
+            let kind = match &propdef.data {
+                LexiconDataType::Ref(r) => format!("ref {}", r.reference),
+                LexiconDataType::String(s) if s.format.is_some() => format!("string {}", s.format.as_deref().unwrap_or_default()),
+                data => data.type_name().to_string(),
+            };
+            let why = if is_nullable {
+                "nullable"
+            } else if is_option {
+                "not required"
+            } else if !is_required {
+                "not required, with a default"
+            } else {
+                "required"
+            };
+            eprintln!("trace: {}#{}: {}.{}: {} -> {} ({})", ctx.nsid, ctx.def, defname, propname, kind, final_type, why);
+        }
+
+        // Add serde rename if the field would not be serialized as the property
+        let rust_field_name = struct_field_name(ctx, propname);
+        let serialized_name = if ctx.opts.camel_case {
+            serde_camel_case(rust_field_name.trim_start_matches("r#"))
+        } else {
+            rust_field_name.trim_start_matches("r#").to_string()
+        };
+        let rust_safe_name = if serialized_name != *propname {
+            format!("    #[serde(rename = \"{}\")]\n", propname)
+        } else {
+            "".to_string()
+        };
+
+        // Add the field with its documentation if available
+        if let Some(desc) = &propdef.description {
+            fields_str.push_str(&doc_lines("    ///", desc));
+        }
+        if let LexiconDataType::Blob(b) = &propdef.data {
+            if !b.accept.is_empty() {
+                fields_str.push_str(&format!("    /// Accepted MIME types: {}\n", b.accept.join(", ")));
+            }
+        }
+        if let Some((_, _, literal, _)) = &const_value {
+            fields_str.push_str(&format!(
+                "    // Constant {}: filled in when absent, other values are rejected by validate()\n",
+                literal
+            ));
+        }
+        fields_str.push_str(&rust_safe_name);
+        if is_option && matches!(propdef.data, LexiconDataType::Null) {
+            emit_helper(ctx, "present_null", &mut nested, || PRESENT_NULL.to_string());
+            fields_str.push_str(
+                "    #[serde(default, deserialize_with = \"present_null\", skip_serializing_if = \"Option::is_none\")]\n",
+            );
+        } else if is_option && !is_nullable {
+            // records leave out the fields they do not have, while a None of
+            // a nullable field is an explicit null
+            fields_str.push_str("    #[serde(skip_serializing_if = \"Option::is_none\")]\n");
+        }
+        if is_chrono && is_option {
+            fields_str.push_str("    #[serde(default, with = \"datetime_rfc3339::option\")]\n");
+        } else if is_chrono {
+            fields_str.push_str("    #[serde(with = \"datetime_rfc3339\")]\n");
+        }
+
+        let mut init = None;
+        // a builder has an Option of the type of every field
+        let mut build_init = format!("self.{}", rust_field_name);
+        if let Some(value) = &default_value {
+            let default_fn = format!(
+                "default_{}_{}",
+                snake_case(defname),
+                snake_case(rust_field_name.trim_start_matches("r#"))
+            );
+            fields_str.push_str(&format!("    #[serde(default = \"{}\")]\n", default_fn));
+            nested.push(format!("fn {}() -> {} {{\n    {}\n}}\n\n", default_fn, final_type, value));
+            init = Some(format!("{}()", default_fn));
+            build_init = format!("self.{}.unwrap_or_else({})", rust_field_name, default_fn);
+        } else if is_option {
+            init = Some("None".to_string());
+        } else {
+            if ctx.opts.default_impl {
+                init = type_default(ctx, &ctx.nsid, propdef, &final_type, &mut HashSet::new());
+            }
+            build_init = format!(
+                "self.{}.ok_or(ValidationError {{ field: \"{}\", constraint: Constraint::Required }})?",
+                rust_field_name, propname
+            );
+        }
+        builder_fields.push((rust_field_name.clone(), setter_type, build_init));
+        if let Ok(inits) = &mut defaults {
+            match init {
+                Some(init) => inits.push_str(&format!("            {}: {},\n", rust_field_name, init)),
+                None => defaults = Err(propname.clone()),
+            }
+        }
+
+        fields_str.push_str(&format!("    pub {}: {},\n", rust_field_name, final_type));
+        fields.push(GeneratedField {
+            propname: propname.clone(),
+            field: rust_field_name.clone(),
+            optional: is_option,
+            array: matches!(propdef.data, LexiconDataType::Array(_)),
+        });
+
+        let mut checks = match &propdef.data {
+            LexiconDataType::Integer(i) => integer_checks(propname, i, integer_type(ctx, i)),
+            LexiconDataType::Blob(b) => blob_checks(propname, b),
+            LexiconDataType::Bytes(b) => bytes_checks(propname, b),
+            // values of a string enum are checked by deserialization already
+            // the length of a timestamp depends on how it is written, nothing to check
+            LexiconDataType::String(_) if is_chrono => String::new(),
+            LexiconDataType::String(s) if s.allowed_enum.is_none() && s.knownValues.is_none() => {
+                let is_newtype = s.format.as_deref().and_then(format_newtype_name).is_some();
+                string_checks(propname, s, if is_newtype { "v.0" } else { "v" })
+            },
+            _ => String::new(),
+        };
+        if let Some((_, Some(cond), _, text)) = &const_value {
+            checks.push_str(&check(propname, cond, &format!("Const({:?})", text)));
+        }
+        if !checks.is_empty() {
+            let binding = if is_option {
+                format!("if let Some(v) = &self.{} {{\n", rust_field_name)
+            } else {
+                format!("{{\n            let v = &self.{};\n", rust_field_name)
+            };
+            validations.push_str(&format!("        {}{}        }}\n", binding, checks));
+        }
+    }
+
+    // Generate the struct definition with derive macros
+    let rename_all = if ctx.opts.camel_case { "#[serde(rename_all = \"camelCase\")]\n" } else { "" };
+    out.push(format!(
+        "#[derive(Debug, Clone, Serialize, Deserialize)]\n{}pub struct {} {{\n{}}}\n\n",
+        rename_all,
+        defname,
+        fields_str
+    ));
+    out.push(format!(
+        "impl {} {{\n    /// Check the fields against the constraints of the lexicon.\n    pub fn validate(&self) -> Result<(), ValidationError> {{\n{}        Ok(())\n    }}\n}}\n\n",
+        defname, validations
+    ));
+    if ctx.opts.default_impl {
+        out.push(match defaults {
+            Ok(inits) => format!(
+                "impl Default for {} {{\n    /// The schema defaults and constants, other fields being empty.\n    fn default() -> Self {{\n        {} {{\n{}        }}\n    }}\n}}\n\n",
+                defname, defname, inits
+            ),
+            Err(propname) => format!("// No impl Default for {}: `{}` has no default value\n\n", defname, propname),
+        });
+    }
+    if ctx.opts.builders {
+        out.push(codegen_builder(defname, &builder_fields));
+    }
+    out.extend(nested);
+    fields
+}
+
+/// Generate a builder of the struct, out of its fields: their name, the
+/// type of their setter, and the expression of their value in `build()`.
+/// A field named `build` would have its setter clash with `build()`.
+fn codegen_builder(defname: &str, fields: &[(String, String, String)]) -> String {
+    if fields.iter().any(|(field, _, _)| field == "build") {
+        return format!("// No builder for {}: it has a field named `build`\n\n", defname);
+    }
+    let mut builder_fields = String::new();
+    let mut setters = String::new();
+    let mut inits = String::new();
+    for (field, setter_type, init) in fields {
+        builder_fields.push_str(&format!("    {}: Option<{}>,\n", field, setter_type));
+        setters.push_str(&format!(
+            "    pub fn {}(mut self, value: impl Into<{}>) -> Self {{\n        self.{} = Some(value.into());\n        self\n    }}\n\n",
+            field, setter_type, field
+        ));
+        inits.push_str(&format!("            {}: {},\n", field, init));
+    }
+    format!(
+        "/// A builder of [`{name}`], with a setter per field.\n#[derive(Debug, Clone, Default)]\npub struct {name}Builder {{\n{fields}}}\n\nimpl {name}Builder {{\n{setters}    /// The value, with the schema defaults and constants of the fields not\n    /// set, if all the required fields are and it passes validate()\n    pub fn build(self) -> Result<{name}, ValidationError> {{\n        let value = {name} {{\n{inits}        }};\n        value.validate()?;\n        Ok(value)\n    }}\n}}\n\nimpl {name} {{\n    /// A builder with no field set\n    pub fn builder() -> {name}Builder {{\n        {name}Builder::default()\n    }}\n}}\n\n",
+        name = defname,
+        fields = builder_fields,
+        setters = setters,
+        inits = inits
+    )
+}
+
+/// Converts a value to its representation in a query string.
+const QUERY_VALUE: &str = r#"/// The query string representation of a parameter value.
+pub fn query_value<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        Ok(v) => v.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+"#;
+
+/// Generate a struct for query string parameters, with a `to_query()` that
+/// repeats the key for each element of an array and skips absent values.
+fn codegen_params(ctx: &mut CodegenContext, name: &str, p: &ParamsType, out: &mut Vec<String>) {
+    let o = ObjectType {
+        required: p.required.clone(),
+        nullable: vec![],
+        properties: p.properties.clone(),
+    };
+    let mut pushes = String::new();
+    // parameters are not JSON objects, and have no `$type`
+    for f in codegen_object(ctx, name, &o, false, out) {
+        let push = format!("query.push((\"{}\".to_string(), query_value(v)));", f.propname);
+        let push = match (f.optional, f.array) {
+            (false, false) => format!("let v = &self.{};\n        {}", f.field, push),
+            (true, false) => format!("if let Some(v) = &self.{} {{\n            {}\n        }}", f.field, push),
+            (false, true) => format!("for v in &self.{} {{\n            {}\n        }}", f.field, push),
+            (true, true) => format!(
+                "for v in self.{}.iter().flatten() {{\n            {}\n        }}",
+                f.field, push
+            ),
+        };
+        pushes.push_str(&format!("        {}\n", push));
+    }
+    emit_helper(ctx, "query_value", out, || QUERY_VALUE.to_string());
+    out.push(format!(
+        "impl {} {{\n    /// The parameters as key/value pairs of an XRPC query string.\n    pub fn to_query(&self) -> Vec<(String, String)> {{\n        let mut query = vec![];\n{}        query\n    }}\n}}\n\n",
+        name, pushes
+    ));
+}
+
+/// Generate an enum over the union refs. Unions are open unless marked
+/// `closed`, so by default there is a fallback variant holding whatever
+/// did not match any of the known refs. Each known variant gets an `as_`
+/// and an `into_` method, and a `From` of its type.
+fn codegen_union(ctx: &mut CodegenContext, name: &str, u: &UnionType) -> String {
+    let mut variants = String::new();
+    let mut methods = vec![];
+    let mut from_impls = String::new();
+    let mut from_types = HashSet::new();
+    let mut seen = HashSet::new();
+    let open = u.closed != Some(true);
+    if open {
+        seen.insert("Unknown".to_string());
+    }
+    for reference in &u.refs {
+        let tag = ref_type_tag(&ctx.nsid, reference);
+        let short_name = match tag.split_once('#') {
+            Some((_, fragment)) => fragment,
+            None => tag.rsplit('.').next().unwrap_or(&tag),
+        };
+        let mut variant = variant_name(short_name);
+        while !seen.insert(variant.clone()) {
+            variant.push('_');
+        }
+        let rust_type = ref_type_name(ctx, reference);
+        let boxed = is_recursive_ref(ctx, reference);
+        let (variant_type, as_value, into_value, from_value) = if boxed {
+            (format!("Box<{}>", rust_type), "value.as_ref()", "*value", "Box::new(value)")
+        } else {
+            (rust_type.clone(), "value", "value", "value")
+        };
+        variants.push_str(&format!(
+            "    #[serde(rename = \"{}\")]\n    {}({}),\n",
+            tag,
+            variant,
+            variant_type
+        ));
+        // with a single variant, there is nothing else it could be
+        let other = if open || u.refs.len() > 1 { "\n            _ => None," } else { "" };
+        methods.push(format!(
+            r#"    /// The `{tag}` data, if it is this variant
+    pub fn as_{method}(&self) -> Option<&{rust_type}> {{
+        match self {{
+            {name}::{variant}(value) => Some({as_value}),{other}
+        }}
+    }}
+
+    pub fn into_{method}(self) -> Option<{rust_type}> {{
+        match self {{
+            {name}::{variant}(value) => Some({into_value}),{other}
+        }}
+    }}
+"#,
+            tag = tag,
+            method = snake_case(&variant),
+            rust_type = rust_type,
+            name = name,
+            variant = variant,
+            as_value = as_value,
+            into_value = into_value,
+            other = other
+        ));
+        // null defs are all `()`, so there is no telling which variant it is
+        let is_null = matches!(ctx.lexicons.resolve(reference, &ctx.nsid).map(|d| &d.data), Some(LexiconDataType::Null));
+        if !is_null && from_types.insert(rust_type.clone()) {
+            from_impls.push_str(&format!(
+                "impl From<{rust_type}> for {name} {{\n    fn from(value: {rust_type}) -> Self {{\n        {name}::{variant}({from_value})\n    }}\n}}\n\n",
+                rust_type = rust_type,
+                name = name,
+                variant = variant,
+                from_value = from_value
+            ));
+        }
+    }
+    let mut code = String::new();
+    if open {
+        variants.push_str("    #[serde(untagged)]\n    Unknown(serde_json::Value),\n");
+    }
+    code.push_str(&format!(
+        "#[derive(Debug, Clone, Serialize, Deserialize)]\n#[serde(tag = \"$type\")]\npub enum {} {{\n{}}}\n\n",
+        name,
+        variants
+    ));
+    if open {
+        methods.push(format!(
+            r#"    /// The data of a variant not known at generation time
+    pub fn as_unknown(&self) -> Option<&serde_json::Value> {{
+        match self {{
+            {name}::Unknown(value) => Some(value),
+            _ => None,
+        }}
+    }}
+"#,
+            name = name
+        ));
+    }
+    if !methods.is_empty() {
+        code.push_str(&format!("impl {} {{\n{}}}\n\n", name, methods.join("\n")));
+    }
+    code.push_str(&from_impls);
+    code.push_str(&format!(
+        r#"impl {try_from}<serde_json::Value> for {name} {{
+    type Error = serde_json::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {{
+        serde_json::from_value(value)
+    }}
+}}
+
+"#,
+        try_from = ctx.opts.edition.try_from(),
+        name = name
+    ));
+    code
+}
+
+/// Generate the type of an XRPC request or response body: JSON bodies get
+/// the type of their schema, an object, a union of refs, or an alias of the
+/// type of a ref, anything else is just the raw bytes.
+fn codegen_body(
+    ctx: &mut CodegenContext,
+    name: &str,
+    encoding: &str,
+    schema: &Option<Box<LexiconData>>,
+    out: &mut Vec<String>,
+) {
+    if encoding != "application/json" {
+        out.push(format!("/// Body with encoding `{}`\npub type {} = Vec<u8>;\n\n", encoding, name));
+    } else if let Some(schema) = schema {
+        match &schema.data {
+            LexiconDataType::Ref(r) => {
+                let doc = schema.description.as_deref().map(|desc| doc_lines("///", desc)).unwrap_or_default();
+                out.push(format!("{}pub type {} = {};\n\n", doc, name, ref_type_name(ctx, &r.reference)));
+            }
+            _ => out.extend(codegen_one_def(ctx, name, schema)),
+        }
+    }
+}
+
+/// Generate an enum of the XRPC errors an endpoint may return.
+fn codegen_errors(name: &str, errors: &[SomeError]) -> String {
+    if errors.is_empty() {
+        return String::new();
+    }
+    let mut variants = String::new();
+    for error in errors {
+        if let Some(desc) = &error.description {
+            variants.push_str(&doc_lines("    ///", desc));
+        }
+        variants.push_str(&format!("    {},\n", error.name));
+    }
+    format!(
+        "#[derive(Debug, Clone, Serialize, Deserialize)]\npub enum {} {{\n{}}}\n\n",
+        name, variants
+    )
+}
+
+/// What the generated XRPC calls need: the HTTP requests are left to an
+/// implementation of `XrpcClient` over the HTTP client of choice.
+const XRPC_CLIENT: &str = r#"/// Why an XRPC call failed.
+#[derive(Debug)]
+pub enum XrpcError {
+    /// The request could not be sent, or there was no response
+    Transport(String),
+    /// The response has an error status, with the XRPC error if any
+    Response {
+        status: u16,
+        error: Option<String>,
+        message: Option<String>,
+    },
+    /// A JSON body could not be encoded or decoded
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for XrpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XrpcError::Transport(e) => write!(f, "XRPC request failed: {}", e),
+            XrpcError::Response { status, error, message } => write!(
+                f,
+                "XRPC error {}: {} {}",
+                status,
+                error.as_deref().unwrap_or("-"),
+                message.as_deref().unwrap_or("")
+            ),
+            XrpcError::Json(e) => write!(f, "XRPC body is not valid: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for XrpcError {}
+
+impl From<serde_json::Error> for XrpcError {
+    fn from(e: serde_json::Error) -> Self {
+        XrpcError::Json(e)
+    }
+}
+
+/// The HTTP side of XRPC, for the generated calls. Both methods return the
+/// response body on success, and `XrpcError::Response` on an error status.
+#[allow(async_fn_in_trait)]
+pub trait XrpcClient {
+    /// `GET /xrpc/<nsid>` with the query string
+    async fn query(&self, nsid: &str, params: &[(String, String)]) -> Result<Vec<u8>, XrpcError>;
+
+    /// `POST /xrpc/<nsid>` with the query string, and the body with its
+    /// Content-Type if there is one
+    async fn procedure(
+        &self,
+        nsid: &str,
+        params: &[(String, String)],
+        input: Option<(&str, Vec<u8>)>,
+    ) -> Result<Vec<u8>, XrpcError>;
+}
+
+"#;
+
+/// The Rust type of a JSON body. Bodies other than objects, unions and
+/// refs have no type of their own.
+fn json_body_type(name: &str, schema: &Option<Box<LexiconData>>) -> String {
+    match schema.as_deref().map(|s| &s.data) {
+        Some(LexiconDataType::Object(_)) | Some(LexiconDataType::Union(_)) | Some(LexiconDataType::Ref(_)) => name.to_string(),
+        _ => "serde_json::Value".to_string(),
+    }
+}
+
+/// Generate an async function calling the query or procedure of the
+/// current lexicon, named after its NSID, e.g. `app_bsky_feed_get_timeline`.
+/// An input with a wildcard encoding, as for blob uploads, takes the
+/// actual Content-Type as an argument.
+fn codegen_client_fn(
+    ctx: &mut CodegenContext,
+    defname: &str,
+    params: bool,
+    input: Option<&InputType>,
+    output: Option<&OutputType>,
+    procedure: bool,
+    out: &mut Vec<String>,
+) -> String {
+    emit_helper(ctx, "XrpcClient", out, || XRPC_CLIENT.to_string());
+    let fn_name = ctx.nsid.split('.').map(snake_case).collect::<Vec<_>>().join("_");
+    let mut args = vec!["client: &impl XrpcClient".to_string()];
+    if params {
+        args.push(format!("params: {}", endpoint_type_name(ctx, defname, "Params")));
+    }
+    let query = if params { "&params.to_query()" } else { "&[]" };
+    let call = if procedure {
+        let body = match input {
+            None => "None".to_string(),
+            Some(input) => {
+                let encoding = if input.encoding.contains('*') {
+                    args.push("encoding: &str".to_string());
+                    "encoding".to_string()
+                } else {
+                    format!("{:?}", input.encoding)
+                };
+                if input.encoding == "application/json" {
+                    let input_name = endpoint_type_name(ctx, defname, "Input");
+                    let input_type = json_body_type(&input_name, &input.schema);
+                    args.push(format!("input: &{}", input_type));
+                    format!("Some(({}, serde_json::to_vec(input)?))", encoding)
+                } else {
+                    args.push("input: Vec<u8>".to_string());
+                    format!("Some(({}, input))", encoding)
+                }
+            }
+        };
+        format!("client.procedure(\"{}\", {}, {}).await?", ctx.nsid, query, body)
+    } else {
+        format!("client.query(\"{}\", {}).await?", ctx.nsid, query)
+    };
+    let output_name = endpoint_type_name(ctx, defname, "Output");
+    let (output_type, body) = match output {
+        None => ("()".to_string(), format!("{};\n    Ok(())", call)),
+        Some(output) if output.encoding != "application/json" => (output_name, format!("Ok({})", call)),
+        Some(output) => (
+            json_body_type(&output_name, &output.schema),
+            format!("let body = {};\n    Ok(serde_json::from_slice(&body)?)", call),
+        ),
+    };
+    format!(
+        "/// Call the `{}` {}.\npub async fn {}({}) -> Result<{}, XrpcError> {{\n    {}\n}}\n\n",
+        ctx.nsid,
+        if procedure { "procedure" } else { "query" },
+        fn_name,
+        args.join(", "),
+        output_type,
+        body
+    )
+}
+
+/// Whether codegen_one_def generates code for a def of this type, rather
+/// than a comment saying it is not generated.
+pub fn is_generated(data: &LexiconDataType) -> bool {
+    !matches!(
+        data,
+        LexiconDataType::Bytes(_)
+            | LexiconDataType::Blob(_)
+            | LexiconDataType::Array(_)
+            | LexiconDataType::Ref(_)
+            | LexiconDataType::Unknown
+            | LexiconDataType::CidLink
+    )
+}
+
+/// The `NSID` constant of the module of an endpoint in the atrium style,
+/// where the endpoint is the `main` def.
+fn endpoint_nsid(ctx: &CodegenContext) -> String {
+    if ctx.opts.style == Style::Atrium && ctx.def == "main" {
+        format!("pub const NSID: &str = \"{}\";\n\n", ctx.nsid)
+    } else {
+        String::new()
+    }
+}
+
+fn codegen_one_def(ctx: &mut CodegenContext, defname: &str, def: &LexiconData) -> Vec<String> {
+    let doc = def.description.as_deref().map(|desc| doc_lines("///", desc)).unwrap_or_default();
+    let mut out = vec![];
+    // index of each of the types the def maps to, to document them
+    let mut documented = vec![0];
+    let item = match &def.data {
+        LexiconDataType::Object(o) => {
+            codegen_object(ctx, defname, o, ctx.opts.type_field, &mut out);
+            String::new()
+        },
+        LexiconDataType::Record(r) => {
+            codegen_object(ctx, defname, &r.record, ctx.opts.type_field, &mut out);
+            // The record key policy ("tid", "literal:self", "any", ...) has no Rust
+            // representation, so carry it along as a constant next to the struct.
+            format!(
+                "impl {} {{\n    pub const RECORD_KEY: &'static str = \"{}\";\n}}\n\n",
+                defname,
+                r.key
+            )
+        },
+        LexiconDataType::Union(u) => codegen_union(ctx, defname, u),
+        // A `null` def or field only ever has a JSON null as its value, and
+        // is `()`, which is always written out. This is not the same as a
+        // field that may be absent, which is an Option: an optional `null`
+        // field is `Option<()>`, `Some(())` being a null that is there and
+        // `None` an absent field, which is skipped when serializing. Being
+        // in `nullable` makes no difference to a `null` field.
+        LexiconDataType::Null => format!("pub type {} = ();\n\n", defname),
+        // a scalar def is the type of its field, which for a string enum is
+        // named after the def already, anything else getting an alias. The
+        // constraints of the def are left to the objects with a field of it.
+        LexiconDataType::String(_) | LexiconDataType::Integer(_) | LexiconDataType::Boolean(_) => {
+            let rust_type = field_type(ctx, defname, def, &mut out);
+            if rust_type == defname {
+                documented = vec![out.len() - 1];
+                String::new()
+            } else {
+                documented = vec![out.len()];
+                format!("pub type {} = {};\n\n", defname, rust_type)
+            }
+        },
+        // a unit struct for refs to it, e.g. from unions, carrying the token string
+        LexiconDataType::Token => format!(
+            "#[derive(Debug, Clone, Serialize, Deserialize)]\npub struct {};\n\nimpl {} {{\n    pub const TOKEN: &'static str = \"{}\";\n}}\n\n",
+            defname,
+            defname,
+            ref_type_tag(&ctx.nsid, &format!("#{}", ctx.def))
+        ),
+        LexiconDataType::Params(p) => {
+            codegen_params(ctx, defname, p, &mut out);
+            String::new()
+        },
+        LexiconDataType::Query(q) => {
+            if let Some(params) = &q.parameters {
+                codegen_params(ctx, &endpoint_type_name(ctx, defname, "Params"), params, &mut out);
+            }
+            if let Some(output) = &q.output {
+                documented.push(out.len());
+                codegen_body(ctx, &endpoint_type_name(ctx, defname, "Output"), &output.encoding, &output.schema, &mut out);
+            }
+            let client = if ctx.opts.client {
+                let params = q.parameters.is_some();
+                codegen_client_fn(ctx, defname, params, None, q.output.as_ref(), false, &mut out)
+            } else {
+                String::new()
+            };
+            documented.push(out.len());
+            codegen_errors(&endpoint_type_name(ctx, defname, "Error"), &q.errors) + &client + &endpoint_nsid(ctx)
+        },
+        LexiconDataType::Procedure(p) => {
+            if let Some(params) = &p.parameters {
+                codegen_params(ctx, &endpoint_type_name(ctx, defname, "Params"), params, &mut out);
+            }
+            if let Some(input) = &p.input {
+                documented.push(out.len());
+                codegen_body(ctx, &endpoint_type_name(ctx, defname, "Input"), &input.encoding, &input.schema, &mut out);
+            }
+            if let Some(output) = &p.output {
+                documented.push(out.len());
+                codegen_body(ctx, &endpoint_type_name(ctx, defname, "Output"), &output.encoding, &output.schema, &mut out);
+            }
+            let client = if ctx.opts.client {
+                let params = p.parameters.is_some();
+                codegen_client_fn(ctx, defname, params, p.input.as_ref(), p.output.as_ref(), true, &mut out)
+            } else {
+                String::new()
+            };
+            documented.push(out.len());
+            codegen_errors(&endpoint_type_name(ctx, defname, "Error"), &p.errors) + &client + &endpoint_nsid(ctx)
+        },
+        LexiconDataType::Subscription(sub) => {
+            if let Some(params) = &sub.parameters {
+                codegen_params(ctx, &endpoint_type_name(ctx, defname, "Params"), params, &mut out);
+            }
+            if let Some(message) = &sub.message {
+                documented.push(out.len());
+                out.extend(codegen_one_def(ctx, &endpoint_type_name(ctx, defname, "Message"), &message.schema));
+            }
+            documented.push(out.len());
+            codegen_errors(&endpoint_type_name(ctx, defname, "Error"), &sub.errors) + &endpoint_nsid(ctx)
+        },
+        x => {
+            documented.clear();
+            let def = ctx.def_ref();
+            ctx.warn(Warning::NotGenerated { def, kind: x.type_name().to_string() });
+            format!("/* {}: {:#?} - not generated */\n", defname, x)
+        }
+    };
+    if !item.is_empty() {
+        out.push(item);
+    }
+    documented.dedup();
+    for i in documented {
+        if let Some(item) = out.get_mut(i) {
+            item.insert_str(0, &doc);
+        }
+    }
+    out
+}
+
+/// Turn a description into comment lines starting with `prefix`, e.g.
+/// `    ///`. Carriage returns, which rustc rejects in doc comments, are
+/// dropped, and code fences without a language are marked as `text` so
+/// that rustdoc does not run them as doctests. The rest is markdown anyway.
+fn doc_lines(prefix: &str, desc: &str) -> String {
+    let mut doc = String::new();
+    let mut in_fence = false;
+    for line in desc.trim_end().split('\n') {
+        let mut line = line.replace('\r', "").trim_end().to_string();
+        if line.trim_start().starts_with("```") {
+            if !in_fence && line.trim_start() == "```" {
+                line.push_str("text");
+            }
+            in_fence = !in_fence;
+        }
+        if line.is_empty() {
+            doc.push_str(&format!("{}\n", prefix));
+        } else {
+            doc.push_str(&format!("{} {}\n", prefix, line));
+        }
+    }
+    doc
+}
+
+/// Imports needed by the generated code, once per module.
+pub const PRELUDE: &str = "use serde::{Deserialize, Serialize};\n\n";
+
+/// Imports of the alloc types, which are not in the prelude of a `no_std`
+/// crate.
+const ALLOC_PRELUDE: &str = "#[allow(unused_imports)]\nuse alloc::{boxed::Box, string::{String, ToString}, vec, vec::Vec};\n\n";
+
+/// The imports of a module, with those of the alloc types for `no_std`.
+///
+/// Everything the generated code does only needs `core` and `alloc`, so it
+/// builds without `std` as long as its dependencies do: serde and
+/// serde_json with `default-features = false` and their `alloc` feature,
+/// chrono for `--chrono` with `alloc` and `serde`, erased-serde for the
+/// record registry with `alloc`. validate() is available, counting the
+/// graphemes with unicode-segmentation, which is `no_std` itself; the
+/// `Error` impls use `core::error::Error`, stable since Rust 1.81. The
+/// crate root needs `#![no_std]` and `extern crate alloc;`.
+pub fn prelude(no_std: bool) -> String {
+    if no_std {
+        format!("{}{}", PRELUDE, ALLOC_PRELUDE)
+    } else {
+        PRELUDE.to_string()
+    }
+}
+
+/// The code with its `std::` paths in `core::` instead, for `no_std`.
+fn core_paths(code: &str, no_std: bool) -> String {
+    if !no_std {
+        return code.to_string();
+    }
+    let mut out = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(pos) = rest.find("std::") {
+        let (before, after) = rest.split_at(pos);
+        out.push_str(before);
+        let in_path = out.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+        out.push_str(if in_path { "std::" } else { "core::" });
+        rest = &after["std::".len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The doc comment of the module for a lexicon file, with `prefix` being
+/// `//!` for a module of its own, or `//` for a part of a bigger one.
+pub fn module_doc(lex: &LexiconFile, prefix: &str) -> String {
+    let mut doc = format!("{} `{}`\n", prefix, lex.id);
+    if let Some(desc) = &lex.description {
+        doc.push_str(&format!("{}\n", prefix));
+        doc.push_str(&doc_lines(prefix, desc));
+    }
+    doc.push_str(&format!("{}\n", prefix));
+    match &lex.revision {
+        Some(revision) => doc.push_str(&format!("{} Revision {}, SHA-256 `{}`\n", prefix, revision, lexicon_hash(lex))),
+        None => doc.push_str(&format!("{} SHA-256 `{}`\n", prefix, lexicon_hash(lex))),
+    }
+    doc.push('\n');
+    doc
+}
+
+/// The constants of the module for a lexicon file, for the users of the
+/// code to tell which version of the lexicon it was generated from. Only
+/// for a module of its own, as they would collide in a bigger one.
+pub fn lexicon_consts(lex: &LexiconFile) -> String {
+    format!(
+        "/// The revision of the lexicon the module was generated from\npub const LEXICON_REVISION: Option<&str> = {};\n/// The SHA-256 of the lexicon, in hex, as computed by `blexicon::codegen::lexicon_hash`\npub const LEXICON_HASH: &str = \"{}\";\n\n",
+        match &lex.revision {
+            Some(revision) => format!("Some({:?})", revision),
+            None => "None".to_string(),
+        },
+        lexicon_hash(lex)
+    )
+}
+
+/// The SHA-256 of the lexicon as canonical JSON, in hex. The keys of the
+/// objects are sorted and there is no whitespace, so the hash only changes
+/// with the lexicon, not with the layout of its file.
+pub fn lexicon_hash(lex: &LexiconFile) -> String {
+    use sha2::{Digest, Sha256};
+
+    let value = serde_json::to_value(lex).expect("a lexicon is valid JSON");
+    let mut json = String::new();
+    canonical_json(&value, &mut json);
+    Sha256::digest(json.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn canonical_json(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                canonical_json(item, out);
+            }
+            out.push(']');
+        }
+        serde_json::Value::Object(map) => {
+            let keys: BTreeSet<&String> = map.keys().collect();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::from(key.as_str()).to_string());
+                out.push(':');
+                canonical_json(&map[key], out);
+            }
+            out.push('}');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// Helper types that are not lexicon data, and keep their own derives.
+const NO_EXTRA_DERIVES: &[&str] = &[
+    "pub enum Constraint",
+    "pub struct ValidationError",
+    "pub enum XrpcError",
+    "pub struct EmptyString",
+    "pub struct InvalidFormat",
+];
+
+/// Append the extra traits to each top level derive list in the code,
+/// except for `Default` on enums, which can't be derived without choosing
+/// a default variant, on structs with an `impl Default` already, and on
+/// `NonEmptyString`, which has no empty value.
+fn add_derives(code: &str, derives: &[String]) -> String {
+    if derives.is_empty() {
+        return code.to_string();
+    }
+    let lines: Vec<&str> = code.lines().collect();
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let Some(list) = line.strip_prefix("#[derive(").and_then(|l| l.strip_suffix(")]")) else {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        };
+        let item = lines[i + 1..].iter().find(|l| !l.starts_with("#[")).unwrap_or(&"");
+        let is_enum = item.starts_with("pub enum");
+        let mut traits: Vec<&str> = list.split(", ").collect();
+        for derive in derives {
+            let has_default_impl = item
+                .strip_prefix("pub struct ")
+                .and_then(|rest| rest.split([' ', '(']).next())
+                .is_some_and(|name| code.contains(&format!("impl Default for {} {{", name)));
+            let skip = traits.contains(&derive.as_str())
+                || ((is_enum || has_default_impl || item.starts_with("pub struct NonEmptyString")) && derive == "Default")
+                || NO_EXTRA_DERIVES.iter().any(|name| item.starts_with(name));
+            if !skip {
+                traits.push(derive);
+            }
+        }
+        out.push_str(&format!("#[derive({})]\n", traits.join(", ")));
+    }
+    out
+}
+
+/// Whether the name could be a derivable trait, possibly with a path.
+pub fn is_trait_path(name: &str) -> bool {
+    name.split("::").all(|segment| {
+        segment.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Name of the module of the helpers shared by all the lexicon modules
+const COMMON_MODULE: &str = "common";
+
+/// The module with the code of all the helpers registered so far.
+pub fn common_module(ctx: &CodegenContext) -> String {
+    let helpers: String = ctx.common_helpers.values().map(String::as_str).collect();
+    format!(
+        "/// Helper types shared by all the lexicon modules\npub mod {} {{\n{}{}}}\n\n",
+        COMMON_MODULE,
+        prelude(ctx.opts.no_std),
+        core_paths(&add_derives(&helpers, &ctx.opts.derives), ctx.opts.no_std)
+    )
+}
+
+/// The import of the `common` module into the module of the lexicon `nsid`.
+pub fn common_use(nsid: &str) -> String {
+    format!(
+        "#[allow(unused_imports)]\nuse {}{}::*;\n\n",
+        "super::".repeat(nsid.split('.').count()),
+        COMMON_MODULE
+    )
+}
+
+const RECORD_REGISTRY: &str = r#"/// Why `deserialize_record` failed.
+#[derive(Debug)]
+pub enum RecordError {
+    /// No record type was generated for the NSID
+    UnknownNsid(String),
+    /// The JSON is not a record of the type
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::UnknownNsid(nsid) => write!(f, "no record type for {}", nsid),
+            RecordError::Json(e) => write!(f, "invalid record: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+"#;
+
+/// The registry of the record types among the lexicons: their NSIDs, and
+/// `deserialize_record`, which turns the JSON of a record of a collection
+/// into its type, as a `dyn erased_serde::Serialize` to write it out again.
+/// It goes at the top level of the output. With modules, the types are in
+/// the modules of their lexicon files, and that top level has no prelude,
+/// so the registry imports the alloc types itself for `no_std`.
+pub fn record_registry(ctx: &CodegenContext, lexicons: &[LexiconFile]) -> String {
+    let (modules, no_std) = (ctx.opts.modules, ctx.opts.no_std);
+    let mut nsids = String::new();
+    let mut arms = String::new();
+    for lex in lexicons {
+        let Some(def @ LexiconData { data: LexiconDataType::Record(_), .. }) = lex.defs.get("main") else {
+            continue;
+        };
+        let name = ctx.type_name(&lex.id, "main", Some(def));
+        let path = if modules {
+            let segments: Vec<String> = lex.id.split('.').map(snake_case).collect();
+            format!("{}::{}", segments.join("::"), name)
+        } else {
+            name
+        };
+        nsids.push_str(&format!("    \"{}\",\n", lex.id));
+        arms.push_str(&format!(
+            "        \"{}\" => Ok(Box::new(serde_json::from_slice::<{}>(bytes).map_err(RecordError::Json)?)),\n",
+            lex.id, path
+        ));
+    }
+    let imports = if no_std && modules { ALLOC_PRELUDE } else { "" };
+    let registry = format!(
+        "{}{}/// The NSIDs of the record types, which `deserialize_record` knows.\npub const RECORD_NSIDS: &[&str] = &[\n{}];\n\n/// Deserialize the JSON of a record of the collection `nsid` into its type.\npub fn deserialize_record(nsid: &str, bytes: &[u8]) -> Result<Box<dyn erased_serde::Serialize>, RecordError> {{\n    match nsid {{\n{}        _ => Err(RecordError::UnknownNsid(nsid.to_string())),\n    }}\n}}\n\n",
+        imports, RECORD_REGISTRY, nsids, arms
+    );
+    core_paths(&registry, no_std)
+}
+
+/// Why `codegen_dir` failed.
+#[derive(Debug)]
+pub enum CodegenError {
+    /// The lexicons could not be loaded
+    Load(ResolveError),
+    /// A generated file could not be written
+    Write { path: PathBuf, error: std::io::Error },
+}
+
+impl std::fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodegenError::Load(e) => write!(f, "{}", e),
+            CodegenError::Write { path, error } => write!(f, "could not write {}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+/// Generate the code of all the lexicons below `input` into `output`, in
+/// modules following the NSIDs, with `output/mod.rs` declaring them: this
+/// is the call for a build script to make. Each module is a file, or a
+/// directory if it has modules of its own, and the modules option is
+/// always on, so that refs between them resolve. The code is not run
+/// through rustfmt.
+///
+/// The same lexicons give the same files, and files already holding their
+/// code are not written again, so that cargo does not rebuild for nothing.
+/// As the files of modules are looked for next to the file declaring them,
+/// `output` is meant to be in the source tree, e.g. `src/lexicons` for a
+/// `mod lexicons;`, rather than in the `OUT_DIR` of the build script.
+pub fn codegen_dir(input: &Path, output: &Path, opts: &CodegenOptions) -> Result<(), CodegenError> {
+    let resolver = LexiconResolver::load_dir(input).map_err(CodegenError::Load)?;
+    let mut lexicons: Vec<LexiconFile> = resolver.lexicons().cloned().collect();
+    lexicons.sort_by(|a, b| a.id.cmp(&b.id));
+    let opts = CodegenOptions {
+        modules: true,
+        ..opts.clone()
+    };
+    let mut ctx = CodegenContext::new(opts, resolver);
+    let mut tree = ModuleTree::default();
+    for lex in &lexicons {
+        ctx.new_scope();
+        let code = ctx.generate_module(lex).to_code();
+        let common = if ctx.opts.common { common_use(&lex.id) } else { String::new() };
+        tree.insert(&lex.id, &format!("{}{}{}{}{}", module_doc(lex, "//!"), prelude(ctx.opts.no_std), common, lexicon_consts(lex), code));
+    }
+    if ctx.opts.common {
+        tree.code = common_module(&ctx);
+    }
+    tree.write_files(output)
+}
+
+/// Write the file, unless it already has the contents.
+fn write_if_changed(path: &Path, contents: &str) -> Result<(), CodegenError> {
+    if std::fs::read_to_string(path).is_ok_and(|current| current == contents) {
+        return Ok(());
+    }
+    let write_error = |error| CodegenError::Write {
+        path: path.to_path_buf(),
+        error,
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    std::fs::write(path, contents).map_err(write_error)
+}
+
+/// Generated code of several lexicon files, arranged by NSID segments.
+#[derive(Debug, Default)]
+pub struct ModuleTree {
+    code: String,
+    children: BTreeMap<String, ModuleTree>,
+}
+
+impl ModuleTree {
+    pub fn insert(&mut self, nsid: &str, code: &str) {
+        let mut node = self;
+        for segment in nsid.split('.') {
+            node = node.children.entry(snake_case(segment)).or_default();
+        }
+        node.code.push_str(code);
+    }
+
+    pub fn render(&self, out: &mut String) {
+        out.push_str(&self.code);
+        for (name, child) in &self.children {
+            out.push_str(&format!("pub mod {} {{\n", name));
+            child.render(out);
+            out.push_str("}\n");
+        }
+    }
+
+    /// Write the tree into the directory, with its code in `mod.rs`,
+    /// followed by the declarations of the modules below it.
+    fn write_files(&self, dir: &Path) -> Result<(), CodegenError> {
+        let mut code = self.code.clone();
+        for (name, child) in &self.children {
+            code.push_str(&format!("pub mod {};\n", name));
+            if child.children.is_empty() {
+                write_if_changed(&dir.join(format!("{}.rs", name)), &child.code)?;
+            } else {
+                child.write_files(&dir.join(name))?;
+            }
+        }
+        write_if_changed(&dir.join("mod.rs"), &code)
+    }
+}
+
+/// The name serde gives a field with `rename_all = "camelCase"`: the
+/// letter after each underscore is capitalized, the first one lowercased.
+fn serde_camel_case(field: &str) -> String {
+    let mut pascal = String::new();
+    let mut capitalize = true;
+    for c in field.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            pascal.push(c.to_ascii_uppercase());
+            capitalize = false;
+        } else {
+            pascal.push(c);
+        }
+    }
+    match pascal.get(..1) {
+        Some(first) => first.to_ascii_lowercase() + &pascal[1..],
+        None => pascal,
+    }
+}
+
+/// Turn a name like `strongRef` into snake_case.
+fn snake_case(segment: &str) -> String {
+    let mut name = String::new();
+    for c in segment.chars() {
+        if c.is_ascii_uppercase() {
+            if !name.is_empty() {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        } else if c == '-' {
+            name.push('_');
+        } else {
+            name.push(c);
+        }
+    }
+    name
+}
@@ -0,0 +1,210 @@
+//! Conversion of lexicon defs to JSON Schema (draft 2020-12) documents.
+//!
+//! Each data def gets a document with the `$id` `lex:<nsid>/<name>`, which
+//! is what refs to it point to. Queries, procedures and subscriptions are
+//! not data, and have no schema.
+//!
+//! Lexicon string lengths are in UTF-8 bytes, while JSON Schema counts
+//! characters, so `minLength` and `maxLength` are only approximations.
+
+use crate::resolve::split_ref;
+use crate::{LexiconData, LexiconDataType, LexiconFile, ObjectType, UnionType};
+use serde_json::{json, Map, Value};
+
+pub const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// The `$id` of the document for a def
+pub fn schema_id(nsid: &str, name: &str) -> String {
+    format!("lex:{}/{}", nsid, name)
+}
+
+/// The JSON Schema documents of the data defs of a lexicon file, keyed by
+/// `<nsid>#<name>`.
+pub fn json_schemas(lex: &LexiconFile) -> Map<String, Value> {
+    let mut schemas = Map::new();
+    for (name, def) in &lex.defs {
+        if let LexiconDataType::Query(_)
+        | LexiconDataType::Procedure(_)
+        | LexiconDataType::Subscription(_) = def.data
+        {
+            continue;
+        }
+        let mut schema = json!({
+            "$schema": DRAFT,
+            "$id": schema_id(&lex.id, name),
+        });
+        let def_schema = data_schema(&lex.id, name, def);
+        if let (Some(schema), Value::Object(def_schema)) = (schema.as_object_mut(), def_schema) {
+            schema.extend(def_schema);
+        }
+        schemas.insert(format!("{}#{}", lex.id, name), schema);
+    }
+    schemas
+}
+
+/// The `$type` of the target of a ref, `#main` being implicit
+fn type_tag(nsid: &str, name: &str) -> String {
+    if name == "main" {
+        nsid.to_string()
+    } else {
+        format!("{}#{}", nsid, name)
+    }
+}
+
+/// Add the members to the object if they have a value
+fn extend(schema: &mut Value, members: Vec<(&str, Value)>) {
+    if let Some(object) = schema.as_object_mut() {
+        for (key, value) in members {
+            if !value.is_null() {
+                object.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+/// The schema of a def or a field, for a def named `name` in lexicon `nsid`
+fn data_schema(nsid: &str, name: &str, def: &LexiconData) -> Value {
+    use LexiconDataType::*;
+    let mut schema = match &def.data {
+        String(s) => {
+            let mut schema = json!({ "type": "string" });
+            let format = match s.format.as_deref() {
+                Some("datetime") => Some("date-time"),
+                format => format,
+            };
+            extend(
+                &mut schema,
+                vec![
+                    ("format", json!(format)),
+                    ("minLength", json!(s.minLength)),
+                    ("maxLength", json!(s.maxLength)),
+                    ("enum", json!(s.allowed_enum)),
+                    ("const", json!(s.constant)),
+                    ("default", json!(s.default)),
+                    ("examples", json!(s.knownValues)),
+                ],
+            );
+            schema
+        }
+        Integer(i) => {
+            let mut schema = json!({ "type": "integer" });
+            extend(
+                &mut schema,
+                vec![
+                    ("minimum", json!(i.minimum)),
+                    ("maximum", json!(i.maximum)),
+                    ("enum", json!(i.allowed_enum)),
+                    ("const", json!(i.constant)),
+                    ("default", json!(i.default)),
+                ],
+            );
+            schema
+        }
+        Boolean(b) => {
+            let mut schema = json!({ "type": "boolean" });
+            extend(
+                &mut schema,
+                vec![("const", json!(b.constant)), ("default", json!(b.default))],
+            );
+            schema
+        }
+        Null => json!({ "type": "null" }),
+        Unknown => json!({ "type": "object" }),
+        Token => json!({ "type": "string", "const": type_tag(nsid, name) }),
+        Bytes(_) => json!({
+            "type": "object",
+            "required": ["$bytes"],
+            "properties": { "$bytes": { "type": "string", "contentEncoding": "base64" } },
+        }),
+        CidLink => cid_link_schema(),
+        Blob(b) => {
+            let mut size = json!({ "type": "integer" });
+            extend(&mut size, vec![("maximum", json!(b.maxSize))]);
+            json!({
+                "type": "object",
+                "required": ["$type", "ref", "mimeType", "size"],
+                "properties": {
+                    "$type": { "const": "blob" },
+                    "ref": cid_link_schema(),
+                    "mimeType": { "type": "string" },
+                    "size": size,
+                },
+            })
+        }
+        Array(a) => {
+            let mut schema = json!({
+                "type": "array",
+                "items": data_schema(nsid, name, &a.items),
+            });
+            extend(&mut schema, vec![("maxItems", json!(a.maxLength))]);
+            schema
+        }
+        Object(o) => object_schema(nsid, name, o),
+        Record(r) => object_schema(nsid, name, &r.record),
+        Params(p) => {
+            let o = ObjectType {
+                required: p.required.clone(),
+                nullable: vec![],
+                properties: p.properties.clone(),
+            };
+            object_schema(nsid, name, &o)
+        }
+        Ref(r) => {
+            let (ref_nsid, ref_name) = split_ref(&r.reference, nsid);
+            json!({ "$ref": schema_id(ref_nsid, ref_name) })
+        }
+        Union(u) => union_schema(nsid, u),
+        Query(_) | Procedure(_) | Subscription(_) => json!({}),
+    };
+    extend(&mut schema, vec![("description", json!(def.description))]);
+    schema
+}
+
+fn cid_link_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["$link"],
+        "properties": { "$link": { "type": "string" } },
+    })
+}
+
+fn object_schema(nsid: &str, name: &str, o: &ObjectType) -> Value {
+    let mut properties = Map::new();
+    for (propname, prop) in &o.properties {
+        let mut schema = data_schema(nsid, name, prop);
+        if o.nullable.contains(propname) {
+            schema = json!({ "oneOf": [schema, { "type": "null" }] });
+        }
+        properties.insert(propname.clone(), schema);
+    }
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !o.required.is_empty() {
+        extend(&mut schema, vec![("required", json!(o.required))]);
+    }
+    schema
+}
+
+/// One of the refs, told apart by `$type`. An open union also takes any
+/// other object with a `$type`.
+fn union_schema(nsid: &str, u: &UnionType) -> Value {
+    let mut tags = vec![];
+    let mut variants = vec![];
+    for reference in &u.refs {
+        let (ref_nsid, ref_name) = split_ref(reference, nsid);
+        let tag = type_tag(ref_nsid, ref_name);
+        variants.push(json!({
+            "$ref": schema_id(ref_nsid, ref_name),
+            "required": ["$type"],
+            "properties": { "$type": { "const": tag } },
+        }));
+        tags.push(tag);
+    }
+    if u.closed != Some(true) {
+        variants.push(json!({
+            "type": "object",
+            "required": ["$type"],
+            "not": { "properties": { "$type": { "enum": tags } } },
+        }));
+    }
+    json!({ "oneOf": variants })
+}
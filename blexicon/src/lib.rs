@@ -0,0 +1,261 @@
+//! The data model of atproto Lexicon files, as read by serde.
+//!
+//! ```
+//! let lex = blexicon::parse_lexicon(r#"{
+//!     "lexicon": 1,
+//!     "id": "com.example.thing",
+//!     "defs": { "main": { "type": "object", "properties": {} } }
+//! }"#).unwrap();
+//! assert_eq!(lex.id, "com.example.thing");
+//! ```
+#![allow(non_snake_case)]
+use linked_hash_map::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+
+pub mod codegen;
+pub mod json_schema;
+pub mod resolve;
+pub mod validate;
+pub use resolve::{LexiconResolver, ResolveError};
+pub use validate::{validate_value, ValidationIssue};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BytesType {
+    pub maxLength: Option<u64>,
+    pub minLength: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArrayType {
+    pub items: Box<LexiconData>,
+    pub maxLength: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordType {
+    pub key: String,
+    pub record: ObjectType,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeType {
+    pub format: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SomeError {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriptionType {
+    pub parameters: Option<ParamsType>,
+    pub message: Option<SubscribeMessage>,
+    #[serde(default)]
+    pub errors: Vec<SomeError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputType {
+    pub description: Option<String>,
+    pub encoding: String,
+    /// An object, a ref or a union of refs
+    #[serde(default)]
+    pub schema: Option<Box<LexiconData>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryType {
+    #[serde(default)]
+    pub parameters: Option<ParamsType>,
+    pub output: Option<OutputType>,
+    #[serde(default)]
+    pub errors: Vec<SomeError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputType {
+    pub encoding: String,
+    #[serde(default)]
+    pub schema: Option<Box<LexiconData>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcedureType {
+    #[serde(default)]
+    pub parameters: Option<ParamsType>,
+    #[serde(default)]
+    pub input: Option<InputType>,
+    pub output: Option<OutputType>,
+    #[serde(default)]
+    pub errors: Vec<SomeError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeMessage {
+    pub schema: Box<LexiconData>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParamsType {
+    #[serde(default)]
+    pub required: Vec<String>,
+    pub properties: LinkedHashMap<String, LexiconData>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnionType {
+    pub refs: Vec<String>,
+    pub closed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectType {
+    #[serde(default)]
+    pub required: Vec<String>,
+    #[serde(default)]
+    pub nullable: Vec<String>,
+    pub properties: LinkedHashMap<String, LexiconData>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StringType {
+    pub format: Option<String>,
+    pub maxLength: Option<i64>,
+    pub minLength: Option<i64>,
+    pub maxGraphemes: Option<i64>,
+    pub minGraphemes: Option<i64>,
+    pub knownValues: Option<Vec<String>>,
+    #[serde(default, rename = "enum")]
+    pub allowed_enum: Option<Vec<String>>,
+    pub default: Option<String>,
+    #[serde(rename = "const")]
+    pub constant: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BooleanType {
+    pub default: Option<bool>,
+    #[serde(rename = "const")]
+    pub constant: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegerType {
+    pub minimum: Option<i64>,
+    pub maximum: Option<i64>,
+    #[serde(default, rename = "enum")]
+    pub allowed_enum: Option<Vec<i64>>,
+    pub default: Option<i64>,
+    #[serde(rename = "const")]
+    pub constant: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefType {
+    #[serde(rename = "ref")]
+    pub reference: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobType {
+    pub accept: Vec<String>,
+    pub maxSize: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "lowercase")]
+pub enum LexiconDataType {
+    String(StringType),
+    Bytes(BytesType),
+    Blob(BlobType),
+    Array(ArrayType),
+    Record(RecordType),
+    Subscription(SubscriptionType),
+    Query(QueryType),
+    Procedure(ProcedureType),
+    Params(ParamsType),
+    Union(UnionType),
+    Object(ObjectType),
+    Ref(RefType),
+    Unknown,
+    Boolean(BooleanType),
+    Null,
+    #[serde(rename = "cid-link")]
+    CidLink,
+    Integer(IntegerType),
+    Token,
+}
+
+impl LexiconDataType {
+    /// The `type` of the definition in the lexicon, e.g. `cid-link`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LexiconDataType::String(_) => "string",
+            LexiconDataType::Bytes(_) => "bytes",
+            LexiconDataType::Blob(_) => "blob",
+            LexiconDataType::Array(_) => "array",
+            LexiconDataType::Record(_) => "record",
+            LexiconDataType::Subscription(_) => "subscription",
+            LexiconDataType::Query(_) => "query",
+            LexiconDataType::Procedure(_) => "procedure",
+            LexiconDataType::Params(_) => "params",
+            LexiconDataType::Union(_) => "union",
+            LexiconDataType::Object(_) => "object",
+            LexiconDataType::Ref(_) => "ref",
+            LexiconDataType::Unknown => "unknown",
+            LexiconDataType::Boolean(_) => "boolean",
+            LexiconDataType::Null => "null",
+            LexiconDataType::CidLink => "cid-link",
+            LexiconDataType::Integer(_) => "integer",
+            LexiconDataType::Token => "token",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LexiconData {
+    #[serde(flatten)]
+    pub data: LexiconDataType,
+    pub description: Option<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LexiconTypeDef {}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LexiconFile {
+    pub lexicon: u32,
+    pub id: String,
+    pub revision: Option<String>,
+    pub description: Option<String>,
+    pub defs: LinkedHashMap<String, LexiconData>,
+}
+
+/// Why a lexicon could not be read.
+#[derive(Debug)]
+pub enum Error {
+    /// Not valid JSON, or not a valid lexicon
+    Parse(serde_json::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parse(e) => Some(e),
+        }
+    }
+}
+
+/// Parse the JSON of a lexicon file.
+pub fn parse_lexicon(json: &str) -> Result<LexiconFile, Error> {
+    serde_json::from_str(json).map_err(Error::Parse)
+}
@@ -1,289 +1,578 @@
+
+use anyhow::Context;
+use blexicon::resolve::{lexicon_files, split_ref};
+use blexicon::{parse_lexicon, LexiconFile, LexiconResolver};
 use clap::Parser as ClapParser;
-use linked_hash_map::LinkedHashMap;
+use blexicon::codegen::{
+    common_module, common_use, is_generated, is_trait_path, lexicon_consts, module_doc, prelude, record_registry, CodegenContext, CodegenOptions, Edition, ModuleTree, Style, Warning,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::time::{Duration, SystemTime};
+use std::collections::BTreeSet;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct BytesType {
-    pub maxLength: Option<u64>,
-    pub minLength: Option<u64>,
-}
+/// The source name standing for standard input
+const STDIN_SOURCE: &str = "-";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ArrayType {
-    items: Box<LexiconData>,
-    pub maxLength: Option<u64>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct RecordType {
-    key: String,
-    record: ObjectType,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct TimeType {
-    format: String,
+fn source_name(fname: &str) -> &str {
+    if fname == STDIN_SOURCE {
+        "<stdin>"
+    } else {
+        fname
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SomeError {
-    name: String,
-    description: Option<String>,
+/// Read and parse one lexicon file.
+fn load_file(fname: &str) -> Result<LexiconFile, anyhow::Error> {
+    let name = source_name(fname);
+    let data = if fname == STDIN_SOURCE {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(fname)
+    }
+    .with_context(|| format!("could not read {}", name))?;
+    let lex = parse_lexicon(&data).with_context(|| format!("could not parse {}", name))?;
+    // println!("read: {:#?}", &lex);
+    Ok(lex)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SubscriptionType {
-    parameters: Option<ObjectType>,
-    message: Option<SubscribeMessage>,
-    errors: Vec<SomeError>,
+/// Expand a source argument into lexicon file names: a directory stands
+/// for all the `*.json` files below it, in a stable order.
+fn source_files(source: &str) -> Result<Vec<String>, anyhow::Error> {
+    let path = std::path::Path::new(source);
+    if source == STDIN_SOURCE || !path.is_dir() {
+        return Ok(vec![source.to_string()]);
+    }
+    let files = lexicon_files(path).with_context(|| format!("could not read directory {}", source))?;
+    Ok(files.iter().map(|f| f.to_string_lossy().to_string()).collect())
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct OutputType {
-    description: Option<String>,
-    encoding: String,
-    #[serde(default)]
-    schema: Option<Box<LexiconData>>, // FIXME: spec says it's object, but then says it is object, a ref or union of refs...
+/// Where the generated module for lexicon `id`, one of `file_count` files,
+/// should be written, if anywhere. The module tree always goes into a single file.
+fn output_path(opts: &Opts, file_count: usize, id: &str) -> Option<std::path::PathBuf> {
+    let output = std::path::Path::new(opts.output.as_ref()?);
+    if file_count > 1 && !opts.modules {
+        Some(output.join(format!("{}.rs", id.replace('.', "_"))))
+    } else {
+        Some(output.to_path_buf())
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct QueryType {
-    #[serde(default)]
-    parameters: Option<ObjectType>,
-    output: Option<OutputType>,
-    #[serde(default)]
-    errors: Vec<SomeError>,
+/// Format the code with rustfmt, `$BLEXICON_RUSTFMT` if set. Should that
+/// fail, the code is returned as is, after a warning.
+fn rustfmt(code: &str, edition: Edition) -> String {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let rustfmt = std::env::var("BLEXICON_RUSTFMT").unwrap_or_else(|_| "rustfmt".to_string());
+    let child = Command::new(&rustfmt)
+        .args(["--edition", edition.as_str()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Warning: could not run {}, output is not formatted: {}", rustfmt, e);
+            return code.to_string();
+        }
+    };
+    // rustfmt only starts writing once it read everything, so this can't deadlock
+    let written = child.stdin.take().unwrap().write_all(code.as_bytes());
+    let output = child.wait_with_output();
+    match (written, output) {
+        (Ok(()), Ok(output)) if output.status.success() => String::from_utf8_lossy(&output.stdout).to_string(),
+        (_, Ok(output)) => {
+            eprintln!(
+                "Warning: {} failed, the generated code may be invalid:\n{}",
+                rustfmt,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            code.to_string()
+        }
+        (_, Err(e)) => {
+            eprintln!("Warning: {} failed, output is not formatted: {}", rustfmt, e);
+            code.to_string()
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct InputType {
-    encoding: String,
-    #[serde(default)]
-    schema: Option<Box<LexiconData>>,
+/// Write the code to where `output_path` says, or to stdout.
+fn write_output(opts: &Opts, file_count: usize, id: &str, code: &str) -> bool {
+    let code = match &opts.wrap_module {
+        Some(name) => wrap_module(name, opts.feature.as_deref(), code),
+        None => code.to_string(),
+    };
+    let code = if opts.no_format { code } else { rustfmt(&code, opts.edition) };
+    store_output(opts, output_path(opts, file_count, id).as_deref(), &code)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ProcedureType {
-    #[serde(default)]
-    parameters: Option<ObjectType>,
-    #[serde(default)]
-    input: Option<InputType>,
-    output: Option<OutputType>,
-    #[serde(default)]
-    errors: Vec<SomeError>,
+/// Nest the code in a module, only compiled with the feature if there is one.
+/// Refs between modules are relative, so they still resolve inside.
+fn wrap_module(name: &str, feature: Option<&str>, code: &str) -> String {
+    let cfg = feature
+        .map(|feature| format!("#![cfg(feature = {:?})]\n", feature))
+        .unwrap_or_default();
+    format!("pub mod {} {{\n{}{}}}\n", name, cfg, code)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct SubscribeMessage {
-    schema: Box<LexiconData>,
+/// Write the output to the file, or stdout if there is none. With --check,
+/// compare it with the file instead, printing a diff. Returns whether the
+/// file is up to date, which it is not if it could not be written.
+fn store_output(opts: &Opts, path: Option<&std::path::Path>, code: &str) -> bool {
+    let Some(path) = path else {
+        println!("{}", code);
+        return true;
+    };
+    if opts.check {
+        // a missing file is diffed as empty, and so not up to date
+        let current = match std::fs::read_to_string(path) {
+            Ok(current) => current,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                eprintln!("Error: could not read {}: {}", path.display(), e);
+                return false;
+            }
+        };
+        if current == code {
+            return true;
+        }
+        let name = path.display().to_string();
+        let diff = similar::TextDiff::from_lines(current.as_str(), code);
+        print!("{}", diff.unified_diff().header(&name, &name));
+        eprintln!("{} is not up to date", name);
+        return false;
+    }
+    let written = match path.parent() {
+        Some(parent) => std::fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+    .and_then(|()| std::fs::write(path, code));
+    if let Err(e) = written {
+        eprintln!("Error: could not write {}: {}", path.display(), e);
+        return false;
+    }
+    println!("Wrote {}", path.display());
+    true
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ParamsType {
-    required: Vec<String>,
-    properties: LinkedHashMap<String, LexiconData>,
+/// Keep only the def given to --only and the defs it needs. It is either
+/// `nsid#name`, an NSID for its main def, or the name of a def of one of
+/// the sources, as long as only one has it.
+fn only_defs(
+    only: &str,
+    resolver: &LexiconResolver,
+    lexicons: &[LexiconFile],
+) -> Result<Vec<LexiconFile>, anyhow::Error> {
+    let (nsid, name) = if resolver.get(only).is_some() || (only.contains('#') && !only.starts_with('#')) {
+        split_ref(only, "")
+    } else {
+        let name = only.trim_start_matches('#');
+        let owners: Vec<&str> = lexicons
+            .iter()
+            .filter(|lex| lex.defs.contains_key(name))
+            .map(|lex| lex.id.as_str())
+            .collect();
+        match owners[..] {
+            [nsid] => (nsid, name),
+            [] => anyhow::bail!("--only {}: no such def in the sources", only),
+            _ => anyhow::bail!("--only {}: ambiguous, it is a def of {}", only, owners.join(", ")),
+        }
+    };
+    resolver
+        .try_resolve(&format!("{}#{}", nsid, name), nsid)
+        .with_context(|| format!("--only {}", only))?;
+    Ok(keep_defs(&resolver.dependencies(nsid, name), lexicons))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct UnionType {
-    refs: Vec<String>,
-    closed: Option<bool>,
+/// Keep only the lexicons under the --namespace, e.g. `app.bsky.feed` or
+/// `app.bsky.feed.*`, and the defs of other lexicons they need. Prints how
+/// many defs are left.
+fn namespace_defs(
+    namespace: &str,
+    resolver: &LexiconResolver,
+    lexicons: &[LexiconFile],
+) -> Result<Vec<LexiconFile>, anyhow::Error> {
+    let prefix = namespace.trim_end_matches(".*");
+    let inside = |nsid: &str| nsid == prefix || nsid.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('.'));
+    let mut needed = BTreeSet::new();
+    for lex in lexicons.iter().filter(|lex| inside(&lex.id)) {
+        for name in lex.defs.keys() {
+            needed.extend(resolver.dependencies(&lex.id, name));
+        }
+    }
+    if needed.is_empty() {
+        anyhow::bail!("--namespace {}: no lexicon of the sources is in it", namespace);
+    }
+    let total: usize = lexicons.iter().map(|lex| lex.defs.len()).sum();
+    let outside = needed.iter().filter(|(nsid, _)| !inside(nsid)).count();
+    eprintln!(
+        "Generating {} defs in {}, and {} defs of other namespaces they need, skipping {}",
+        needed.len() - outside,
+        prefix,
+        outside,
+        total - needed.len()
+    );
+    Ok(keep_defs(&needed, lexicons))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ObjectType {
-    #[serde(default)]
-    required: Vec<String>,
-    #[serde(default)]
-    nullable: Vec<String>,
-    properties: LinkedHashMap<String, LexiconData>,
+/// The lexicons with only the defs that are needed, leaving out those with
+/// none.
+fn keep_defs(needed: &BTreeSet<(String, String)>, lexicons: &[LexiconFile]) -> Vec<LexiconFile> {
+    let mut kept = vec![];
+    for lex in lexicons {
+        let defs = lex
+            .defs
+            .iter()
+            .filter(|(name, _)| needed.contains(&(lex.id.clone(), name.to_string())))
+            .map(|(name, def)| (name.clone(), def.clone()))
+            .collect::<linked_hash_map::LinkedHashMap<_, _>>();
+        if !defs.is_empty() {
+            kept.push(LexiconFile { defs, ..lex.clone() });
+        }
+    }
+    kept
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct StringType {
-    format: Option<String>,
-    maxLength: Option<i64>,
-    minLength: Option<i64>,
-    maxGraphemes: Option<i64>,
-    minGraphemes: Option<i64>,
-    knownValues: Option<Vec<String>>,
-    #[serde(default, rename = "enum")]
-    allowed_enum: Option<Vec<String>>,
-    default: Option<String>,
-    #[serde(rename = "const")]
-    constant: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum, Serialize, Deserialize)]
+enum Emit {
+    /// Rust types with serde derives
+    Rust,
+    /// A JSON Schema document per def, keyed by `<nsid>#<name>`
+    JsonSchema,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct BooleanType {
-    default: Option<bool>,
-    #[serde(rename = "const")]
-    constant: Option<bool>,
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum, Serialize, Deserialize)]
+enum RenameAll {
+    #[clap(name = "camelCase")]
+    #[serde(rename = "camelCase")]
+    CamelCase,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct IntegerType {
-    minimum: Option<i64>,
-    maximum: Option<i64>,
-    #[serde(default, rename = "enum")]
-    allowed_enum: Option<Vec<i64>>,
-    default: Option<i64>,
-    #[serde(rename = "const")]
-    constant: Option<i64>,
+fn default_emit() -> Emit {
+    Emit::Rust
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct RefType {
-    #[serde(rename = "ref")]
-    reference: String,
+/// Generate the Rust code of the lexicons, and write it out, adding the
+/// warnings of all the modules to `warnings`. Returns whether all the
+/// output files were up to date.
+fn emit_rust(opts: &Opts, ctx: &mut CodegenContext, lexicons: &[LexiconFile], warnings: &mut Vec<Warning>) -> bool {
+    let mut up_to_date = true;
+    let mut tree = ModuleTree::default();
+    let separate_files = opts.output.is_some() && lexicons.len() > 1 && !opts.modules;
+    let mut combined = String::new();
+    for lex in lexicons {
+        if opts.modules || separate_files {
+            // each module is a separate scope, so it needs its own helpers
+            ctx.new_scope();
+        }
+        let module = ctx.generate_module(lex);
+        for warning in &module.warnings {
+            // the defs not generated are left for the summary
+            if !matches!(warning, Warning::NotGenerated { .. }) {
+                eprintln!("Warning: {}", warning);
+            }
+        }
+        warnings.extend(module.warnings.iter().cloned());
+        let code = module.to_code();
+        if opts.modules {
+            let common = if opts.consolidate { common_use(&lex.id) } else { String::new() };
+            tree.insert(&lex.id, &format!("{}{}{}{}{}", module_doc(lex, "//!"), prelude(opts.no_std), common, lexicon_consts(lex), code));
+        } else if separate_files {
+            let code = format!("{}{}{}{}", module_doc(lex, "//!"), prelude(opts.no_std), lexicon_consts(lex), code);
+            up_to_date &= write_output(opts, lexicons.len(), &lex.id, &code);
+        } else if lexicons.len() == 1 {
+            combined = format!("{}{}{}{}", module_doc(lex, "//!"), prelude(opts.no_std), lexicon_consts(lex), code);
+        } else {
+            combined.push_str(&format!("{}{}", module_doc(lex, "//"), code));
+        }
+    }
+    if opts.modules {
+        if opts.consolidate {
+            combined.push_str(&common_module(ctx));
+        }
+        if opts.record_registry {
+            combined.push_str(&record_registry(ctx, lexicons));
+        }
+        tree.render(&mut combined);
+    } else if lexicons.len() > 1 {
+        combined.insert_str(0, &prelude(opts.no_std));
+    }
+    if opts.record_registry && !opts.modules {
+        if separate_files {
+            eprintln!("Warning: no record registry with a file per lexicon, it needs --modules");
+        } else {
+            combined.push_str(&record_registry(ctx, lexicons));
+        }
+    }
+    if !separate_files {
+        up_to_date &= write_output(opts, lexicons.len(), "", &combined);
+    }
+    up_to_date
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct BlobType {
-    accept: Vec<String>,
-    maxSize: Option<i64>,
+/// Write the JSON Schema documents of all the defs as a single JSON object.
+fn emit_json_schema(opts: &Opts, lexicons: &[LexiconFile]) -> bool {
+    let mut schemas = serde_json::Map::new();
+    for lex in lexicons {
+        schemas.extend(blexicon::json_schema::json_schemas(lex));
+    }
+    let json = serde_json::to_string_pretty(&schemas).unwrap();
+    match &opts.output {
+        Some(path) => store_output(opts, Some(path.as_ref()), &(json + "\n")),
+        None => store_output(opts, None, &json),
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "lowercase")]
-enum LexiconDataType {
-    String(StringType),
-    Bytes(BytesType),
-    Blob(BlobType),
-    Array(ArrayType),
-    Record(RecordType),
-    Subscription(SubscriptionType),
-    Query(QueryType),
-    Procedure(ProcedureType),
-    Params(ParamsType),
-    Union(UnionType),
-    Object(ObjectType),
-    Ref(RefType),
-    Unknown,
-    Boolean(BooleanType),
-    Null,
-    #[serde(rename = "cid-link")]
-    CidLink,
-    Integer(IntegerType),
-    Token,
+/// Print a table of the unresolved refs, of the defs not generated and of
+/// the consts and defaults left out or not among their values, if there are
+/// any, to stderr.
+fn print_summary(warnings: &[Warning]) {
+    let mut unresolved = vec![];
+    let mut not_generated = vec![];
+    let mut invalid_consts = vec![];
+    let mut invalid_defaults = vec![];
+    for warning in warnings {
+        match warning {
+            Warning::UnresolvedRef { def, reference, missing } => unresolved.push([def.as_str(), reference, missing]),
+            Warning::NotGenerated { def, kind } => not_generated.push([def.as_str(), kind]),
+            Warning::InvalidConst { def, property, value, reason } => {
+                invalid_consts.push([def.as_str(), property, value, reason])
+            }
+            Warning::InvalidDefault { def, property, value, reason } => {
+                invalid_defaults.push([def.as_str(), property, value, reason])
+            }
+        }
+    }
+    if !unresolved.is_empty() {
+        eprintln!("\n{} unresolved refs:", unresolved.len());
+        print_table(&["DEF", "REF", "MISSING"], &unresolved);
+    }
+    if !not_generated.is_empty() {
+        eprintln!("\n{} defs not generated:", not_generated.len());
+        print_table(&["DEF", "TYPE"], &not_generated);
+    }
+    if !invalid_consts.is_empty() {
+        eprintln!("\n{} invalid consts:", invalid_consts.len());
+        print_table(&["DEF", "PROPERTY", "CONST", "REASON"], &invalid_consts);
+    }
+    if !invalid_defaults.is_empty() {
+        eprintln!("\n{} invalid defaults:", invalid_defaults.len());
+        print_table(&["DEF", "PROPERTY", "DEFAULT", "REASON"], &invalid_defaults);
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct LexiconData {
-    #[serde(flatten)]
-    data: LexiconDataType,
-    description: Option<String>,
-}
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct LexiconTypeDef {}
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct LexiconFile {
-    pub lexicon: u32,
-    pub id: String,
-    pub revision: Option<String>,
-    pub description: Option<String>,
-    pub defs: LinkedHashMap<String, LexiconData>,
+/// Print the rows to stderr in columns as wide as their widest cell.
+fn print_table<const N: usize>(header: &[&str; N], rows: &[[&str; N]]) {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in std::iter::once(header).chain(rows) {
+        let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:width$}", cell, width = width)).collect();
+        eprintln!("    {}", cells.join("  ").trim_end());
+    }
 }
 
-fn codegen_one_def(defname: &str, def: &LexiconData) -> String {
-    match &def.data {
-        LexiconDataType::Object(o) => {
-            let mut fields_str = String::new();
-            for (propname, propdef) in &o.properties {
-                let is_required = o.required.contains(propname);
-                let is_nullable = o.nullable.contains(propname);
-                // Determine the Rust type based on the property definition
-                let rust_type = match &propdef.data {
-                    LexiconDataType::String(_) => "String".to_string(),
-                    LexiconDataType::Integer(_) => "i64".to_string(),
-                    LexiconDataType::Boolean(_) => "bool".to_string(),
-                    LexiconDataType::Array(arr) => {
-                        let inner_type = match &arr.items.data {
-                            LexiconDataType::String(_) => "String".to_string(),
-                            LexiconDataType::Integer(_) => "i64".to_string(),
-                            LexiconDataType::Boolean(_) => "bool".to_string(),
-                            LexiconDataType::Ref(r) => r.reference.split("#").last().unwrap_or(&r.reference).to_string(),
-                            _ => "String".to_string() // Default fallback
-                        };
-                        format!("Vec<{}>", inner_type)
-                    },
-                    LexiconDataType::Ref(r) => r.reference.split("#").last().unwrap_or(&r.reference).to_string(),
-                    LexiconDataType::CidLink => "String".to_string(),
-                    LexiconDataType::Bytes(_) => "Vec<u8>".to_string(),
-                    LexiconDataType::Object(inner_obj) => {
-                        // For nested objects, we'll create a new type name based on the parent and property name
-                        format!("{}{}", defname, propname.chars().next().unwrap().to_uppercase().collect::<String>() + &propname[1..])
-                    },
-                    _ => "String".to_string() // Default fallback
-                };
-
-                // Build the type with Option wrapper if needed
-                let final_type = if !is_required || is_nullable {
-                    format!("Option<{}>", rust_type)
-                } else {
-                    rust_type
-                };
-
-                // Add serde rename if the property name isn't valid Rust
-                let rust_safe_name = if propname.contains('-') || propname.contains('.') {
-                    format!("    #[serde(rename = \"{}\")]\n", propname)
-                } else {
-                    "".to_string()
-                };
-
-                // Add the field with its documentation if available
-                if let Some(desc) = &propdef.description {
-                    fields_str.push_str(&format!("    /// {}\n", desc));
-                }
-                fields_str.push_str(&rust_safe_name);
-
-                // Convert property name to valid Rust identifier
-                let rust_field_name = propname.replace('-', "_").replace('.', "_");
-                fields_str.push_str(&format!("    pub {}: {},\n", rust_field_name, final_type));
-            }
-
-            // Generate the struct definition with derive macros
-            format!(
-                "#[derive(Debug, Clone, Serialize, Deserialize)]\npub struct {} {{\n{}}}\n\n",
-                defname,
-                fields_str
-            )
-        },
-        LexiconDataType::Union(u) => {
-            let mut variants = String::new();
-            for reference in &u.refs {
-                let variant_name = reference.split('#').last().unwrap_or(reference);
-                variants.push_str(&format!("    {},\n", variant_name));
-            }
-            format!(
-                "#[derive(Debug, Clone, Serialize, Deserialize)]\n#[serde(tag = \"type\")]\npub enum {} {{\n{}}}\n\n",
-                defname,
-                variants
-            )
-        },
-        x => {
-            format!("/* {}: {:#?} - not generated */\n", defname, x)
+/// Print a line per def of the lexicons: its ref, its type and whether Rust
+/// code is generated for it, e.g. `app.bsky.feed.post#main record generated`.
+fn list_defs(lexicons: &[LexiconFile]) {
+    for lex in lexicons {
+        for (name, def) in &lex.defs {
+            let generated = if is_generated(&def.data) { "generated" } else { "not-generated" };
+            println!("{}#{} {} {}", lex.id, name, def.data.type_name(), generated);
         }
     }
-    .to_string()
 }
 
 /// This program aims to compile a .json lexicon file into an Rust source code.
 #[derive(Debug, Clone, ClapParser, Serialize, Deserialize)]
 #[clap(version = "0.0.1", author = "Andrew Yourtchenko <ayourtch@gmail.com>")]
 struct Opts {
-    /// Target hostname to do things on
+    /// Lexicon files to compile, directories are searched for `*.json` files
+    /// and `-` reads a single lexicon from stdin
     #[clap()]
     source: Vec<String>,
 
+    /// Read a lexicon from stdin, same as giving `-` as a source
+    #[clap(long)]
+    #[serde(default)]
+    stdin: bool,
+
+    /// What to generate
+    #[clap(long, arg_enum, default_value = "rust")]
+    #[serde(default = "default_emit")]
+    emit: Emit,
+
+    /// Write the generated code to this file instead of stdout. With several
+    /// sources, this is a directory receiving one file per lexicon id.
+    #[clap(long)]
+    output: Option<String>,
+
+    /// Only generate this def and the ones it refers to, e.g. `#main`, `post`
+    /// or `app.bsky.feed.post#replyRef`
+    #[clap(long)]
+    only: Option<String>,
+
+    /// Only generate the lexicons under this NSID prefix, e.g. `app.bsky.feed`,
+    /// and the defs of other namespaces they need. Refs are still resolved
+    /// against all the sources
+    #[clap(long)]
+    namespace: Option<String>,
+
+    /// Put all the generated code in a `pub mod` of this name
+    #[clap(long)]
+    wrap_module: Option<String>,
+
+    /// Only compile the module of --wrap-module with this cargo feature
+    #[clap(long, requires = "wrap-module")]
+    feature: Option<String>,
+
+    /// Nest the generated code of each lexicon in modules following its
+    /// NSID, e.g. `app::bsky::feed::post`, with all sources in one tree.
+    /// Without it, the types whose names clash get the NSID of their
+    /// lexicon, as in `ComExampleNulThing` for `com.example.nul#thing`
+    #[clap(long)]
+    #[serde(default)]
+    modules: bool,
+
+    /// Like --modules, with the helper types (Blob, CidLink, format newtypes,
+    /// ValidationError...) defined once in a `common` module for all of them
+    #[clap(long)]
+    #[serde(default)]
+    consolidate: bool,
+
+    /// Also generate an async function calling each query and procedure,
+    /// through an `XrpcClient` trait to implement over an HTTP client
+    #[clap(long)]
+    #[serde(default)]
+    client: bool,
+
+    /// Extra traits for all generated types to derive, e.g. `PartialEq,Eq,Hash`.
+    /// Types holding arbitrary data (`unknown` fields and open unions) contain
+    /// a serde_json::Value, which does not implement Hash, PartialOrd or Ord.
+    /// Default is not derived for enums, so structs using them can't have it
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default)]
+    derive: Vec<String>,
+
+    /// Give each struct an `impl Default` filling in the schema defaults and
+    /// constants, with other fields empty; validate() tells if that is enough.
+    /// Structs with a required enum or union field have no such impl
+    #[clap(long)]
+    #[serde(default)]
+    derive_default_impl: bool,
+
+    /// Make `datetime` strings `chrono::DateTime<chrono::Utc>` rather than a
+    /// `Datetime` string newtype. The generated code needs the chrono crate
+    /// with its serde feature
+    #[clap(long)]
+    #[serde(default)]
+    chrono: bool,
+
+    /// Make the integers with a minimum of 0 or more `u64` rather than `i64`,
+    /// e.g. the byte offsets of rich text facets
+    #[clap(long)]
+    #[serde(default)]
+    unsigned: bool,
+
+    /// Make the struct fields snake_case, with a serde rename for each field
+    /// named otherwise in the lexicon
+    #[clap(long)]
+    #[serde(default)]
+    snake_case_fields: bool,
+
+    /// Give the structs a serde `rename_all`, only `camelCase` being known,
+    /// with snake_case fields renamed one by one only where the lexicon does
+    /// not follow it, e.g. `$type`
+    #[clap(long, arg_enum)]
+    rename_all: Option<RenameAll>,
+
+    /// Make the plain strings with a minLength or minGraphemes of 1 or more a
+    /// `NonEmptyString`, which can't be deserialized or created from an empty
+    /// string, rather than a `String`. validate() still checks the lengths.
+    /// The format newtypes, e.g. `Did`, then check the shape of the string
+    /// they are parsed from with `FromStr`
+    #[clap(long)]
+    #[serde(default)]
+    strict_newtypes: bool,
+
+    /// Give each struct a builder, e.g. `Post::builder().text("hi").build()`,
+    /// with a setter per field. build() fills in the schema defaults and
+    /// constants, and fails on a required field not set or on validate()
+    #[clap(long)]
+    #[serde(default)]
+    builders: bool,
+
+    /// Generate code for a `#![no_std]` crate with `extern crate alloc;`:
+    /// the paths are in `core`, and each module imports `Box`, `String`,
+    /// `ToString`, `Vec` and `vec!` from `alloc`. All of the code is then
+    /// available, validate() included, given serde and serde_json with their `alloc`
+    /// features rather than `std`, and the same for chrono and erased-serde
+    /// if needed. The `Error` impls need Rust 1.81 or later
+    #[clap(long)]
+    #[serde(default)]
+    no_std: bool,
+
+    /// Generate `deserialize_record`, which deserializes the JSON of a record
+    /// of any of the record types into a `Box<dyn erased_serde::Serialize>`,
+    /// given the NSID of its collection. The generated code then needs the
+    /// erased-serde crate
+    #[clap(long)]
+    #[serde(default)]
+    record_registry: bool,
+
+    /// Give the object structs an optional `r#type` field for the `$type`
+    /// of the object, which is then written back as it was read. Within a
+    /// union, the `$type` is the tag of the variant, and the field is None
+    #[clap(long)]
+    #[serde(default)]
+    with_type_field: bool,
+
+    /// The edition of the crate the generated code goes into. Before 2021,
+    /// `TryFrom` is not in the prelude, so the code names it by its path
+    #[clap(long, arg_enum, default_value = "2021")]
+    #[serde(default)]
+    edition: Edition,
+
+    /// Instead of generating anything, list the defs of the sources, with
+    /// their type and whether code is generated for them
+    #[clap(long)]
+    #[serde(default)]
+    list: bool,
+
+    /// The naming conventions of the types: with `atrium`, those of the
+    /// atrium-api crate, e.g. `app::bsky::feed::post::Record` and
+    /// `app::bsky::feed::get_timeline::Parameters`, which implies --modules
+    #[clap(long, arg_enum, default_value = "blexicon")]
+    #[serde(default)]
+    style: Style,
+
+    /// Do not write the output, but compare it with the --output files, print
+    /// a diff and fail if they are not up to date
+    #[clap(long)]
+    #[serde(default)]
+    check: bool,
+
+    /// Exit with an error if any ref is unresolved, any def is not generated
+    /// or any const or default is not a value it can have, as listed in the
+    /// summary printed at the end of the run
+    #[clap(long)]
+    #[serde(default)]
+    strict: bool,
+
+    /// Do not run the generated code through rustfmt (`$BLEXICON_RUSTFMT`)
+    #[clap(long)]
+    #[serde(default)]
+    no_format: bool,
+
     /// Override options from this yaml/json file
     #[clap(short, long)]
     options_override: Option<String>,
 
-    /// A level of verbosity, and can be used multiple times
+    /// A level of verbosity, and can be used multiple times. With -vv, the
+    /// Rust type chosen for each property is traced to stderr
     #[clap(short, long, parse(from_occurrences))]
     verbose: i32,
 }
@@ -293,12 +582,10 @@ fn main() {
 
     // allow to load the options, so far there is no good built-in way
     let opts = if let Some(fname) = &opts.options_override {
-        if let Ok(data) = std::fs::read_to_string(&fname) {
-            let res = serde_json::from_str(&data);
-            if res.is_ok() {
-                res.unwrap()
-            } else {
-                serde_yaml::from_str(&data).unwrap()
+        if let Ok(data) = std::fs::read_to_string(fname) {
+            match serde_json::from_str(&data) {
+                Ok(res) => res,
+                Err(_) => serde_yaml::from_str(&data).unwrap(),
             }
         } else {
             opts
@@ -315,16 +602,119 @@ fn main() {
         println!("{}", data);
     }
 
-    for fname in &opts.source {
-        println!("Reading {}", &fname);
-        if let Ok(data) = std::fs::read_to_string(&fname) {
-            let lex: LexiconFile = serde_json::from_str(&data).unwrap();
-            // println!("read: {:#?}", &lex);
-            for (name, def) in &lex.defs {
-                println!("{}", codegen_one_def(name, def));
+    let opts = Opts {
+        modules: opts.modules || opts.consolidate || opts.style == Style::Atrium,
+        ..opts
+    };
+
+    if let Some(name) = opts.derive.iter().find(|name| !is_trait_path(name)) {
+        eprintln!("Error: --derive {:?} is not a trait name", name);
+        std::process::exit(1);
+    }
+
+    if opts.check && opts.output.is_none() {
+        eprintln!("Error: --check needs --output to compare with");
+        std::process::exit(1);
+    }
+
+    let options = CodegenOptions {
+        derives: opts.derive.clone(),
+        modules: opts.modules,
+        client: opts.client,
+        default_impl: opts.derive_default_impl,
+        common: opts.consolidate,
+        chrono: opts.chrono,
+        unsigned: opts.unsigned,
+        snake_case_fields: opts.snake_case_fields,
+        camel_case: opts.rename_all == Some(RenameAll::CamelCase),
+        edition: opts.edition,
+        style: opts.style,
+        type_field: opts.with_type_field,
+        strict_newtypes: opts.strict_newtypes,
+        builders: opts.builders,
+        no_std: opts.no_std,
+        trace: opts.verbose >= 2,
+    };
+    let mut failures = vec![];
+    let mut files = vec![];
+    if opts.stdin && !opts.source.iter().any(|s| s == STDIN_SOURCE) {
+        files.push(STDIN_SOURCE.to_string());
+    }
+    for source in &opts.source {
+        match source_files(source) {
+            Ok(fnames) => files.extend(fnames),
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                failures.push(source.clone());
             }
-        } else {
-            panic!("Could not read {}", &fname);
         }
     }
+
+    // load everything first, so refs across files can be resolved
+    let mut resolver = LexiconResolver::new();
+    let mut lexicons = vec![];
+    for fname in &files {
+        if fname != STDIN_SOURCE {
+            // keep stdout clean for piping stdin through
+            println!("Reading {}", &fname);
+        }
+        match load_file(fname) {
+            Ok(lex) => {
+                resolver.insert(lex.clone());
+                lexicons.push(lex);
+            }
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                failures.push(source_name(fname).to_string());
+            }
+        }
+    }
+
+    if let Some(namespace) = &opts.namespace {
+        lexicons = match namespace_defs(namespace, &resolver, &lexicons) {
+            Ok(lexicons) => lexicons,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    if let Some(only) = &opts.only {
+        lexicons = match only_defs(only, &resolver, &lexicons) {
+            Ok(lexicons) => lexicons,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let mut warnings = vec![];
+    let up_to_date = if opts.list {
+        list_defs(&lexicons);
+        true
+    } else {
+        match opts.emit {
+            Emit::Rust => emit_rust(&opts, &mut CodegenContext::new(options, resolver), &lexicons, &mut warnings),
+            Emit::JsonSchema => emit_json_schema(&opts, &lexicons),
+        }
+    };
+    warnings.sort();
+    warnings.dedup();
+    print_summary(&warnings);
+    if !failures.is_empty() {
+        eprintln!("Failed to process {} of {} files:", failures.len(), files.len());
+        for fname in &failures {
+            eprintln!("    {}", fname);
+        }
+        std::process::exit(1);
+    }
+    if !up_to_date {
+        std::process::exit(1);
+    }
+    if opts.strict && !warnings.is_empty() {
+        eprintln!("Error: {} warnings in the summary, with --strict", warnings.len());
+        std::process::exit(1);
+    }
 }
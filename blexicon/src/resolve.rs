@@ -0,0 +1,207 @@
+//! Resolution of refs between the defs of a set of lexicon files.
+//!
+//! A ref is `nsid#name`, where the NSID can be left out for a def in the
+//! same file (`#name`) and the name for the `main` def (`nsid`).
+
+use crate::{parse_lexicon, Error, LexiconData, LexiconDataType, LexiconFile};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Why a ref or a lexicon directory could not be resolved.
+#[derive(Debug)]
+pub enum ResolveError {
+    /// A lexicon file could not be read
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// A lexicon file is not valid
+    Parse { path: PathBuf, error: Error },
+    /// The ref is to a lexicon that is not loaded
+    MissingLexicon { reference: String, nsid: String },
+    /// The lexicon of the ref has no such def
+    MissingDef {
+        reference: String,
+        nsid: String,
+        name: String,
+    },
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveError::Io { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            ResolveError::Parse { path, error } => {
+                write!(f, "could not parse {}: {}", path.display(), error)
+            }
+            ResolveError::MissingLexicon { reference, nsid } => {
+                write!(f, "unresolved ref {}, missing NSID {}", reference, nsid)
+            }
+            ResolveError::MissingDef {
+                reference,
+                nsid,
+                name,
+            } => {
+                write!(
+                    f,
+                    "unresolved ref {}, no def {} in {}",
+                    reference, name, nsid
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResolveError::Io { error, .. } => Some(error),
+            ResolveError::Parse { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Split a ref into the NSID and def name it points to, relative to the
+/// lexicon `current_nsid`.
+pub fn split_ref<'a>(reference: &'a str, current_nsid: &'a str) -> (&'a str, &'a str) {
+    let (nsid, name) = reference.split_once('#').unwrap_or((reference, "main"));
+    let nsid = if nsid.is_empty() { current_nsid } else { nsid };
+    (nsid, name)
+}
+
+/// All the `*.json` files below the directory, in a stable order.
+pub fn lexicon_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.path());
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(lexicon_files(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "json") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Collect the refs made by the def, and the types nested in it.
+fn collect_refs<'a>(def: &'a LexiconData, refs: &mut Vec<&'a str>) {
+    use LexiconDataType::*;
+    let mut schemas = vec![];
+    match &def.data {
+        Ref(r) => refs.push(&r.reference),
+        Union(u) => refs.extend(u.refs.iter().map(|r| r.as_str())),
+        Array(a) => schemas.push(&*a.items),
+        Object(o) => schemas.extend(o.properties.values()),
+        Record(r) => schemas.extend(r.record.properties.values()),
+        Params(p) => schemas.extend(p.properties.values()),
+        Query(q) => {
+            schemas.extend(q.parameters.iter().flat_map(|p| p.properties.values()));
+            schemas.extend(q.output.as_ref().and_then(|o| o.schema.as_deref()));
+        }
+        Procedure(p) => {
+            schemas.extend(p.parameters.iter().flat_map(|p| p.properties.values()));
+            schemas.extend(p.input.as_ref().and_then(|i| i.schema.as_deref()));
+            schemas.extend(p.output.as_ref().and_then(|o| o.schema.as_deref()));
+        }
+        Subscription(s) => {
+            schemas.extend(s.parameters.iter().flat_map(|p| p.properties.values()));
+            schemas.extend(s.message.as_ref().map(|m| &*m.schema));
+        }
+        _ => {}
+    }
+    for schema in schemas {
+        collect_refs(schema, refs);
+    }
+}
+
+/// A set of lexicon files, indexed by NSID.
+#[derive(Debug, Clone, Default)]
+pub struct LexiconResolver {
+    lexicons: HashMap<String, LexiconFile>,
+}
+
+impl LexiconResolver {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load all the lexicon files below the directory.
+    pub fn load_dir(dir: &Path) -> Result<Self, ResolveError> {
+        let io_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |error| ResolveError::Io { path, error }
+        };
+        let mut resolver = Self::new();
+        for path in lexicon_files(dir).map_err(io_error(dir))? {
+            let data = std::fs::read_to_string(&path).map_err(io_error(&path))?;
+            let lex = parse_lexicon(&data).map_err(|error| ResolveError::Parse { path, error })?;
+            resolver.insert(lex);
+        }
+        Ok(resolver)
+    }
+
+    /// Add a lexicon, replacing any with the same NSID.
+    pub fn insert(&mut self, lex: LexiconFile) {
+        self.lexicons.insert(lex.id.clone(), lex);
+    }
+
+    pub fn get(&self, nsid: &str) -> Option<&LexiconFile> {
+        self.lexicons.get(nsid)
+    }
+
+    /// The lexicons, in no particular order.
+    pub fn lexicons(&self) -> impl Iterator<Item = &LexiconFile> {
+        self.lexicons.values()
+    }
+
+    /// The def a ref from the lexicon `current_nsid` points to.
+    pub fn resolve(&self, ref_str: &str, current_nsid: &str) -> Option<&LexiconData> {
+        self.try_resolve(ref_str, current_nsid).ok()
+    }
+
+    /// The def `nsid#name` and all the defs it needs, following refs through
+    /// arrays, unions, objects and the bodies of endpoints, as `(nsid, name)`
+    /// pairs. Refs to defs that are not loaded are left out.
+    pub fn dependencies(&self, nsid: &str, name: &str) -> BTreeSet<(String, String)> {
+        let mut found = BTreeSet::new();
+        let mut pending = vec![(nsid.to_string(), name.to_string())];
+        while let Some((nsid, name)) = pending.pop() {
+            let Some(def) = self.get(&nsid).and_then(|lex| lex.defs.get(&name)) else {
+                continue;
+            };
+            if !found.insert((nsid.clone(), name)) {
+                continue;
+            }
+            let mut refs = vec![];
+            collect_refs(def, &mut refs);
+            for reference in refs {
+                let (ref_nsid, ref_name) = split_ref(reference, &nsid);
+                pending.push((ref_nsid.to_string(), ref_name.to_string()));
+            }
+        }
+        found
+    }
+
+    /// Like `resolve`, saying what is missing when the ref dangles.
+    pub fn try_resolve(
+        &self,
+        ref_str: &str,
+        current_nsid: &str,
+    ) -> Result<&LexiconData, ResolveError> {
+        let (nsid, name) = split_ref(ref_str, current_nsid);
+        let lex = self.get(nsid).ok_or_else(|| ResolveError::MissingLexicon {
+            reference: ref_str.to_string(),
+            nsid: nsid.to_string(),
+        })?;
+        lex.defs.get(name).ok_or_else(|| ResolveError::MissingDef {
+            reference: ref_str.to_string(),
+            nsid: nsid.to_string(),
+            name: name.to_string(),
+        })
+    }
+}
@@ -0,0 +1,279 @@
+//! Validation of JSON values, such as records, against a lexicon schema.
+//!
+//! ```
+//! let lex = blexicon::parse_lexicon(r#"{
+//!     "lexicon": 1,
+//!     "id": "com.example.thing",
+//!     "defs": { "main": { "type": "object", "required": ["name"],
+//!         "properties": { "name": { "type": "string", "maxLength": 3 } } } }
+//! }"#).unwrap();
+//! let value = serde_json::json!({ "name": "toolong" });
+//! let issues = blexicon::validate_value(&lex.defs["main"], &value).unwrap_err();
+//! assert_eq!(issues[0].path, "/name");
+//! ```
+//!
+//! Refs and unions need the other defs to be checked, so here a union only
+//! has to be an object with a `$type`, and a ref is not looked into.
+
+use crate::{LexiconData, LexiconDataType, ObjectType};
+use linked_hash_map::LinkedHashMap;
+use serde_json::Value;
+
+/// Something wrong with a value, at a JSON pointer into it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// A JSON pointer (RFC 6901) to the offending node, empty for the root
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {}", path, self.message)
+    }
+}
+
+/// Check the value against the schema, returning all the issues found.
+pub fn validate_value(schema: &LexiconData, value: &Value) -> Result<(), Vec<ValidationIssue>> {
+    let mut issues = vec![];
+    validate(&schema.data, value, "", &mut issues);
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
+
+/// The path of a member of the node at `path`, escaped as RFC 6901 says
+fn child_path(path: &str, key: &str) -> String {
+    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn issue(issues: &mut Vec<ValidationIssue>, path: &str, message: String) {
+    issues.push(ValidationIssue {
+        path: path.to_string(),
+        message,
+    });
+}
+
+/// The JSON type of the value, for messages
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+fn expected(issues: &mut Vec<ValidationIssue>, path: &str, what: &str, value: &Value) {
+    issue(
+        issues,
+        path,
+        format!("expected {}, got {}", what, json_type(value)),
+    );
+}
+
+fn validate(
+    schema: &LexiconDataType,
+    value: &Value,
+    path: &str,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    use LexiconDataType::*;
+    match schema {
+        String(s) => {
+            let Some(text) = value.as_str() else {
+                return expected(issues, path, "a string", value);
+            };
+            // lengths are in UTF-8 bytes
+            let len = text.len() as i64;
+            if let Some(min) = s.minLength.filter(|&min| len < min) {
+                issue(
+                    issues,
+                    path,
+                    format!("string is {} bytes long, minimum is {}", len, min),
+                );
+            }
+            if let Some(max) = s.maxLength.filter(|&max| len > max) {
+                issue(
+                    issues,
+                    path,
+                    format!("string is {} bytes long, maximum is {}", len, max),
+                );
+            }
+            if let Some(allowed) = s
+                .allowed_enum
+                .as_ref()
+                .filter(|a| !a.iter().any(|v| v == text))
+            {
+                issue(
+                    issues,
+                    path,
+                    format!("{:?} is not one of {:?}", text, allowed),
+                );
+            }
+            if let Some(constant) = s.constant.as_ref().filter(|c| *c != text) {
+                issue(
+                    issues,
+                    path,
+                    format!("expected {:?}, got {:?}", constant, text),
+                );
+            }
+        }
+        Integer(i) => {
+            let Some(n) = value.as_i64() else {
+                return expected(issues, path, "an integer", value);
+            };
+            if let Some(min) = i.minimum.filter(|&min| n < min) {
+                issue(
+                    issues,
+                    path,
+                    format!("{} is less than the minimum {}", n, min),
+                );
+            }
+            if let Some(max) = i.maximum.filter(|&max| n > max) {
+                issue(
+                    issues,
+                    path,
+                    format!("{} is more than the maximum {}", n, max),
+                );
+            }
+            if let Some(allowed) = i.allowed_enum.as_ref().filter(|a| !a.contains(&n)) {
+                issue(issues, path, format!("{} is not one of {:?}", n, allowed));
+            }
+            if let Some(constant) = i.constant.filter(|&c| c != n) {
+                issue(issues, path, format!("expected {}, got {}", constant, n));
+            }
+        }
+        Boolean(b) => {
+            let Some(v) = value.as_bool() else {
+                return expected(issues, path, "a boolean", value);
+            };
+            if let Some(constant) = b.constant.filter(|&c| c != v) {
+                issue(issues, path, format!("expected {}, got {}", constant, v));
+            }
+        }
+        Null => {
+            if !value.is_null() {
+                expected(issues, path, "null", value);
+            }
+        }
+        Array(a) => {
+            let Some(items) = value.as_array() else {
+                return expected(issues, path, "an array", value);
+            };
+            if let Some(max) = a.maxLength.filter(|&max| items.len() as u64 > max) {
+                issue(
+                    issues,
+                    path,
+                    format!("array has {} items, maximum is {}", items.len(), max),
+                );
+            }
+            for (i, item) in items.iter().enumerate() {
+                validate(
+                    &a.items.data,
+                    item,
+                    &child_path(path, &i.to_string()),
+                    issues,
+                );
+            }
+        }
+        Object(o) => validate_object(o, value, path, issues),
+        Record(r) => validate_object(&r.record, value, path, issues),
+        Params(p) => validate_properties(&p.properties, &p.required, &[], value, path, issues),
+        Bytes(_) => {
+            if !value.get("$bytes").is_some_and(Value::is_string) {
+                expected(issues, path, "an object with a \"$bytes\" string", value);
+            }
+        }
+        CidLink => {
+            if !value.get("$link").is_some_and(Value::is_string) {
+                expected(issues, path, "an object with a \"$link\" string", value);
+            }
+        }
+        Blob(b) => {
+            if !value.is_object() {
+                return expected(issues, path, "a blob object", value);
+            }
+            let size = value.get("size").and_then(Value::as_i64);
+            if let (Some(size), Some(max)) = (size, b.maxSize) {
+                if size > max {
+                    issue(
+                        issues,
+                        path,
+                        format!("blob is {} bytes, maximum is {}", size, max),
+                    );
+                }
+            }
+            if let Some(mime_type) = value.get("mimeType").and_then(Value::as_str) {
+                if !b.accept.is_empty() && !b.accept.iter().any(|a| mime_type_matches(a, mime_type))
+                {
+                    issue(
+                        issues,
+                        path,
+                        format!("blob type {:?} is not one of {:?}", mime_type, b.accept),
+                    );
+                }
+            }
+        }
+        Union(_) => {
+            if !value.get("$type").is_some_and(Value::is_string) {
+                expected(issues, path, "an object with a \"$type\"", value);
+            }
+        }
+        Unknown => {
+            if !value.is_object() {
+                expected(issues, path, "an object", value);
+            }
+        }
+        Ref(_) => {}
+        Token | Query(_) | Procedure(_) | Subscription(_) => {
+            issue(issues, path, "the schema is not a data type".to_string());
+        }
+    }
+}
+
+/// Whether the MIME type is accepted by the pattern, which may be like `image/*`
+fn mime_type_matches(pattern: &str, mime_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => mime_type.split('/').next() == Some(prefix),
+        None => pattern == "*/*" || pattern == mime_type,
+    }
+}
+
+fn validate_object(o: &ObjectType, value: &Value, path: &str, issues: &mut Vec<ValidationIssue>) {
+    validate_properties(&o.properties, &o.required, &o.nullable, value, path, issues);
+}
+
+fn validate_properties(
+    properties: &LinkedHashMap<String, LexiconData>,
+    required: &[String],
+    nullable: &[String],
+    value: &Value,
+    path: &str,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let Some(object) = value.as_object() else {
+        return expected(issues, path, "an object", value);
+    };
+    for name in required {
+        if !object.contains_key(name) {
+            issue(issues, path, format!("missing required field {:?}", name));
+        }
+    }
+    for (name, schema) in properties {
+        match object.get(name) {
+            None => {}
+            Some(Value::Null) if nullable.contains(name) => {}
+            Some(v) => validate(&schema.data, v, &child_path(path, name), issues),
+        }
+    }
+}
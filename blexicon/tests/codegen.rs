@@ -0,0 +1,473 @@
+//! Checks of the code generated for the fixtures.
+
+mod common;
+
+use common::{field_attrs, fixtures, generate, invalid};
+
+const SKIP_NONE: &str = "#[serde(skip_serializing_if = \"Option::is_none\")]";
+
+#[test]
+fn absent_optional_fields_are_skipped() {
+    let code = generate(&[], &[fixtures().join("com/example/dt.json")]);
+    // optional
+    assert_eq!(field_attrs(&code, "editedAt"), [SKIP_NONE]);
+    // nullable, where a null is meaningful
+    assert_eq!(field_attrs(&code, "deletedAt"), Vec::<&str>::new());
+    // required
+    assert_eq!(field_attrs(&code, "createdAt"), Vec::<&str>::new());
+}
+
+#[test]
+fn unsigned_integers() {
+    let facet = [fixtures().join("app/bsky/richtext/facet.json")];
+    let code = generate(&[], &facet);
+    assert!(code.contains("pub byteStart: i64,"));
+    let code = generate(&["--unsigned"], &facet);
+    assert!(code.contains("pub byteStart: u64,"));
+    // only there to check the minimum of 0, which a u64 can't be below
+    assert!(!code.contains(">= 0"));
+}
+
+#[test]
+fn wrap_module_with_feature() {
+    let strong_ref = [fixtures().join("com/atproto/repo/strongRef.json")];
+    let args = ["--wrap-module", "lexicons", "--feature", "atproto"];
+    let code = generate(&args, &strong_ref);
+    assert!(code.starts_with("pub mod lexicons {\n#![cfg(feature = \"atproto\")]\n"));
+    assert!(code.trim_end().ends_with('}'));
+}
+
+#[test]
+fn snake_case_fields() {
+    let facet = [fixtures().join("app/bsky/richtext/facet.json")];
+    let code = generate(&["--snake-case-fields"], &facet);
+    assert_eq!(
+        field_attrs(&code, "byte_start"),
+        ["#[serde(rename = \"byteStart\")]"]
+    );
+    // with the renaming left to serde, only the names it gets wrong remain
+    let code = generate(&["--rename-all", "camelCase"], &facet);
+    assert!(code.contains("#[serde(rename_all = \"camelCase\")]\npub struct byteSlice {"));
+    assert_eq!(field_attrs(&code, "byte_start"), Vec::<&str>::new());
+}
+
+#[test]
+fn generate_module_api() {
+    use blexicon::codegen::{generate_module, CodegenOptions, Warning};
+
+    let json = std::fs::read_to_string(fixtures().join("app/bsky/feed/post.json")).unwrap();
+    let post = blexicon::parse_lexicon(&json).unwrap();
+    let module = generate_module(&post, &CodegenOptions::default());
+    let names: Vec<&str> = module.items.iter().map(|item| item.name.as_str()).collect();
+    assert_eq!(names, ["Post", "replyRef", "entity", "textSlice"]);
+    assert!(module.helpers.contains("Datetime"));
+    // the refs to other files, such as the reply refs, can't be resolved
+    assert!(module.warnings.contains(&Warning::UnresolvedRef {
+        def: "app.bsky.feed.post#replyRef".to_string(),
+        reference: "com.atproto.repo.strongRef".to_string(),
+        missing: "com.atproto.repo.strongRef".to_string(),
+    }));
+    assert!(module.to_code().contains("pub struct replyRef {"));
+}
+
+#[test]
+fn scalar_defs() {
+    let scalar = [fixtures().join("com/example/scalar.json")];
+    let code = generate(&[], &scalar);
+    assert!(code.contains("/// Who can see the thing\n#[derive(Debug, Clone, Serialize, Deserialize)]\npub enum visibility {"));
+    assert!(code.contains("pub type label = String;"));
+    assert!(code.contains("pub type priority = i64;"));
+    assert!(code.contains("pub type pinned = bool;"));
+    assert!(code.contains("pub type author = Did;"));
+    assert!(code.contains("pub visibility: visibility,"));
+    assert!(!code.contains("not generated"));
+}
+
+#[test]
+fn output_schemas() {
+    let example = fixtures().join("com/example");
+    let sources = [
+        example.join("getObject.json"),
+        example.join("getRef.json"),
+        example.join("getUnion.json"),
+        fixtures().join("com/atproto/repo/strongRef.json"),
+    ];
+    let code = generate(&["--client"], &sources);
+    assert!(code.contains("pub struct GetObjectOutput {"));
+    assert!(code.contains("/// The record found.\npub type GetRefOutput = StrongRef;"));
+    assert!(code.contains("pub enum GetUnionOutput {"));
+    assert!(code.contains("-> Result<GetRefOutput, XrpcError>"));
+    assert!(!code.contains("not generated"));
+}
+
+#[test]
+fn list_defs() {
+    let arrays = [fixtures().join("test/arrays.json")];
+    let list = generate(&["--list"], &arrays);
+    assert_eq!(
+        list,
+        "test.arrays#main object generated\n\
+         test.arrays#a object generated\n\
+         test.arrays#tags array not-generated\n"
+    );
+}
+
+#[test]
+fn namespace_filter() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+        .args(["--list", "--namespace", "app.bsky.feed.*"])
+        .arg(fixtures())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr
+        .contains("Generating 5 defs in app.bsky.feed, and 9 defs of other namespaces they need"));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let defs: Vec<&str> = stdout
+        .lines()
+        .filter(|line| !line.starts_with("Reading "))
+        .map(|line| line.split(' ').next().unwrap())
+        .collect();
+    // the feed, and what it refers to in other namespaces, but no more
+    assert!(defs.contains(&"app.bsky.feed.post#main"));
+    assert!(defs.contains(&"app.bsky.feed.getTimeline#main"));
+    assert!(defs.contains(&"app.bsky.richtext.facet#tag"));
+    assert!(defs.contains(&"com.atproto.repo.strongRef#main"));
+    assert!(!defs.iter().any(|def| def.starts_with("test.")));
+    assert!(!defs.contains(&"com.atproto.repo.createRecord#main"));
+}
+
+#[test]
+fn summary_and_strict() {
+    let sources = [
+        fixtures().join("app/bsky/feed/post.json"),
+        fixtures().join("test/arrays.json"),
+    ];
+    let run = |strict: bool| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+            .args(if strict { &["--strict"][..] } else { &[] })
+            .args(&sources)
+            .output()
+            .unwrap()
+    };
+    let output = run(false);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("\n4 unresolved refs:\n"));
+    assert!(stderr.contains("    app.bsky.feed.post#replyRef  com.atproto.repo.strongRef  com.atproto.repo.strongRef\n"));
+    assert!(stderr.contains("\n1 defs not generated:\n    DEF               TYPE\n    test.arrays#tags  array\n"));
+    // the defs not generated are not warned about as they go
+    assert!(!stderr.contains("Warning: test.arrays"));
+    assert!(!run(true).status.success());
+}
+
+#[test]
+fn flat_names_clash() {
+    let example = fixtures().join("com/example");
+    let nul = [example.join("nul.json")];
+    assert!(generate(&[], &nul).contains("pub struct thing {"));
+    // two `#thing` defs in the same scope, both named after their NSID
+    let sources = [example.join("getObject.json"), example.join("nul.json")];
+    let code = generate(&[], &sources);
+    assert!(code.contains("pub struct ComExampleGetObjectThing {"));
+    assert!(code.contains("pub struct ComExampleNulThing {"));
+    assert!(code.contains("    pub thing: ComExampleGetObjectThing,\n"));
+    assert!(code.contains("    Thing(ComExampleNulThing),\n"));
+    assert!(!code.contains("pub struct thing {"));
+    // the other defs keep their names
+    assert!(code.contains("    Nothing(nothing),\n"));
+    // a module is a scope of its own
+    let code = generate(&["--modules"], &sources);
+    assert_eq!(code.matches("pub struct thing {").count(), 2);
+    // a def named after a helper type, such as `Cid`, gets its NSID too
+    let code = generate(&[], &[fixtures().join("test/cid.json")]);
+    assert!(code.contains("pub struct TestCid {"));
+}
+
+#[test]
+fn consts() {
+    let code = generate(&[], &[fixtures().join("test/consts.json")]);
+    assert!(code.contains("constraint: Constraint::Const(\"fixed\")"));
+    assert!(code.contains("constraint: Constraint::Const(\"3\")"));
+    assert!(!code.contains("Const(\"\\\""));
+    assert!(code.contains("if !(matches!(v, ConstsMode::B)) {"));
+    assert!(code.contains("if !(matches!(v, ConstsLevel::High)) {"));
+}
+
+#[test]
+fn consts_not_among_the_values() {
+    let source = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("bad_consts.json");
+    std::fs::write(
+        &source,
+        r#"{"lexicon": 1, "id": "test.badConsts", "defs": {"main": {"type": "object", "properties": {
+            "mode": {"type": "string", "knownValues": ["a", "b"], "const": "c"},
+            "level": {"type": "string", "enum": ["low", "high"], "const": "mid"}
+        }}}}"#,
+    )
+    .unwrap();
+    let run = |strict: bool| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+            .arg("--no-format")
+            .args(if strict { &["--strict"][..] } else { &[] })
+            .arg(&source)
+            .output()
+            .unwrap()
+    };
+    let output = run(false);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: test.badConsts#main: the const \"c\" of mode is not among its values\n"));
+    assert!(stderr.contains("\n2 invalid consts:\n"));
+    assert!(stderr.contains(
+        "    DEF                  PROPERTY  CONST  REASON\n    test.badConsts#main  level     mid    is not among its values\n"
+    ));
+    let code = String::from_utf8(output.stdout).unwrap();
+    // an open set of values may still hold the const, a closed one never does
+    assert!(code.contains("if !(matches!(v, BadConstsMode::Other(other) if other == \"c\")) {"));
+    assert!(!code.contains("Const(\"mid\")"));
+    assert!(!run(true).status.success());
+}
+
+#[test]
+fn recursive_refs_are_boxed() {
+    let thread = [fixtures().join("test/thread.json")];
+    let code = generate(&[], &thread);
+    // the parent holds a thread, the replies only a Vec of them
+    assert!(code.contains("    Thread(Box<Thread>),\n    #[serde(rename = \"test.thread#notFound\")]\n    NotFound(notFound),"));
+    assert!(code.contains(
+        "pub enum ThreadRepliesItem {\n    #[serde(rename = \"test.thread\")]\n    Thread(Thread),"
+    ));
+    // both the self-ref and the cycle through the thread
+    assert!(code.contains("pub thread: Option<Box<Thread>>,"));
+    assert!(code.contains("pub next: Option<Box<quote>>,"));
+    assert!(code.contains("pub quote: Option<Box<quote>>,"));
+}
+
+#[test]
+fn atrium_style() {
+    let fixtures = fixtures();
+    let sources = [fixtures.join("app"), fixtures.join("com/atproto")];
+    let code = generate(&["--style", "atrium"], &sources);
+    for item in [
+        "pub mod post {",
+        "pub struct Record {",
+        "pub struct ReplyRef {",
+        "pub enum RecordEmbedRefs {",
+        "pub enum MainFeaturesItem {",
+        "pub struct Parameters {",
+        "pub const NSID: &str = \"app.bsky.feed.getTimeline\";",
+        // taken by the format newtype
+        "pub struct handle {",
+    ] {
+        assert!(code.contains(item), "no {} in:\n{}", item, code);
+    }
+    assert!(code
+        .contains("pub root: super::super::super::super::com::atproto::repo::strong_ref::Main,"));
+}
+
+#[test]
+fn lexicon_hash_and_revision() {
+    use blexicon::codegen::{lexicon_consts, lexicon_hash, module_doc};
+
+    let lex = |json: &str| blexicon::parse_lexicon(json).unwrap();
+    let thing = lex(
+        r#"{"lexicon": 1, "id": "com.example.thing", "revision": "3", "defs": {
+        "main": {"type": "object", "properties": {"name": {"type": "string"}}}
+    }}"#,
+    );
+    // the order of the keys and the whitespace don't matter
+    let reordered = lex(
+        r#"{"defs": {"main": {"properties": {"name": {"type": "string"}},
+        "type": "object"}}, "revision": "3", "id": "com.example.thing", "lexicon": 1}"#,
+    );
+    let hash = lexicon_hash(&thing);
+    assert_eq!(hash.len(), 64);
+    assert_eq!(lexicon_hash(&reordered), hash);
+    let changed = lex(
+        r#"{"lexicon": 1, "id": "com.example.thing", "revision": "3", "defs": {
+        "main": {"type": "object", "properties": {"name": {"type": "integer"}}}
+    }}"#,
+    );
+    assert_ne!(lexicon_hash(&changed), hash);
+
+    let consts = lexicon_consts(&thing);
+    assert!(consts.contains("pub const LEXICON_REVISION: Option<&str> = Some(\"3\");"));
+    assert!(consts.contains(&format!("pub const LEXICON_HASH: &str = \"{}\";", hash)));
+    assert!(module_doc(&thing, "//!").contains(&format!("//! Revision 3, SHA-256 `{}`", hash)));
+}
+
+#[test]
+fn builders() {
+    use blexicon::codegen::{generate_module, CodegenOptions};
+
+    let lex = blexicon::parse_lexicon(
+        r#"{"lexicon": 1, "id": "com.example.job", "defs": {
+            "main": {"type": "object", "required": ["name"], "properties": {
+                "name": {"type": "string"},
+                "retries": {"type": "integer", "default": 3},
+                "note": {"type": "string"}
+            }},
+            "step": {"type": "object", "properties": {"build": {"type": "string"}}}
+        }}"#,
+    )
+    .unwrap();
+    let opts = CodegenOptions {
+        builders: true,
+        ..Default::default()
+    };
+    let code = generate_module(&lex, &opts).to_code();
+    assert!(code.contains("pub struct JobBuilder {"));
+    assert!(code.contains("pub fn retries(mut self, value: impl Into<i64>) -> Self {"));
+    assert!(code.contains(
+        "name: self.name.ok_or(ValidationError { field: \"name\", constraint: Constraint::Required })?,"
+    ));
+    assert!(code.contains("retries: self.retries.unwrap_or_else(default_job_retries),"));
+    assert!(code.contains("note: self.note,"));
+    // its setter would be build() too
+    assert!(code.contains("// No builder for step: it has a field named `build`"));
+}
+
+#[test]
+fn field_names() {
+    use blexicon::codegen::field_name;
+
+    let names = [
+        // as they are
+        ("text", "text"),
+        ("byteStart", "byteStart"),
+        // raw identifiers
+        ("type", "r#type"),
+        ("ref", "r#ref"),
+        ("move", "r#move"),
+        ("async", "r#async"),
+        // keywords that can't be raw
+        ("self", "_self"),
+        ("Self", "_Self"),
+        ("crate", "_crate"),
+        ("super", "_super"),
+        ("_", "__"),
+        // not identifiers
+        ("3d", "_3d"),
+        ("0", "_0"),
+        ("app.bsky-x", "app_bsky_x"),
+    ];
+    for (propname, expected) in names {
+        assert_eq!(field_name(propname), expected, "{}", propname);
+    }
+}
+
+#[test]
+fn invalid_defaults() {
+    let run = |strict: bool| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+            .args(["--no-format", "--derive-default-impl", "--builders"])
+            .args(if strict { &["--strict"][..] } else { &[] })
+            .arg(invalid().join("defaults.json"))
+            .output()
+            .unwrap()
+    };
+    let output = run(false);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: test.badDefaults#main: the default \"c\" of kind is not among its values\n"));
+    assert!(stderr.contains("\n2 invalid defaults:\n"));
+    assert!(stderr.contains("    test.badDefaults#main  level     mid      is not among its values\n"));
+    let code = String::from_utf8(output.stdout).unwrap();
+    // with no value to fill in, the field is optional, or required
+    assert!(code.contains("    pub kind: Option<BadDefaultsKind>,\n"));
+    assert!(code.contains("    pub level: BadDefaultsLevel,\n"));
+    assert!(code.contains("level: self.level.ok_or(ValidationError { field: \"level\", constraint: Constraint::Required })?,"));
+    assert!(!code.contains("default_bad_defaults_kind"));
+    assert!(!code.contains("default_bad_defaults_level"));
+    assert!(!code.contains("::Other(\"mid\""));
+    // known values are open to others
+    assert!(code.contains("BadDefaultsKnown::Other(\"c\".to_string())"));
+    assert!(code.contains("    #[serde(default = \"default_bad_defaults_ok\")]\n"));
+    assert!(!run(true).status.success());
+}
+
+#[test]
+fn invalid_datetimes() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+        .args(["--no-format", "--chrono", "--derive-default-impl"])
+        .arg(invalid().join("datetimes.json"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: test.badDatetimes#main: the default \"yesterday\" of at is not an RFC 3339 datetime\n"));
+    assert!(stderr.contains("\n1 invalid defaults:\n"));
+    assert!(stderr.contains("\n1 invalid consts:\n"));
+    assert!(stderr.contains("    test.badDatetimes#main  fixed     2024-13-01T00:00:00Z  is not an RFC 3339 datetime\n"));
+    let code = String::from_utf8(output.stdout).unwrap();
+    // parsed when the default is made, where they would panic
+    assert!(!code.contains("parse_from_rfc3339(\"yesterday\")"));
+    assert!(!code.contains("parse_from_rfc3339(\"2024-13-01T00:00:00Z\")"));
+    assert!(code.contains("    pub at: Option<chrono::DateTime<chrono::Utc>>,\n"));
+    assert!(code.contains("parse_from_rfc3339(\"2024-01-01T00:00:00Z\")"));
+}
+
+#[test]
+fn invalid_empty_strings() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+        .args(["--no-format", "--strict-newtypes", "--derive-default-impl"])
+        .arg(invalid().join("empty.json"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: test.badEmpty#main: the default \"\" of name is empty, below its minimum length\n"));
+    assert!(stderr.contains("Warning: test.badEmpty#main: the const \"\" of title is empty, below its minimum length\n"));
+    let code = String::from_utf8(output.stdout).unwrap();
+    assert!(!code.contains("NonEmptyString::new(\"\")"));
+    assert!(code.contains("    pub name: Option<NonEmptyString>,\n"));
+    assert!(code.contains("    pub title: Option<NonEmptyString>,\n"));
+    assert!(code.contains("NonEmptyString::new(\"x\").unwrap()"));
+}
+
+#[test]
+fn unwritable_output() {
+    let blocker = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("blocker");
+    std::fs::write(&blocker, "a file, where a directory would be\n").unwrap();
+    let output_path = blocker.join("strongRef.rs");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+        .arg("--output")
+        .arg(&output_path)
+        .arg(fixtures().join("com/atproto/repo/strongRef.json"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let error = format!("Error: could not write {}: ", output_path.display());
+    assert!(stderr.contains(&error), "{}", stderr);
+    assert!(!stderr.contains("panicked"));
+}
+
+#[test]
+fn check_unreadable_output() {
+    let strong_ref = fixtures().join("com/atproto/repo/strongRef.json");
+    let check = |output_path: &std::path::Path| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_blexicon"))
+            .arg("--check")
+            .arg("--output")
+            .arg(output_path)
+            .arg(&strong_ref)
+            .output()
+            .unwrap()
+    };
+    let tmp = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    // a directory, which can't be read as the file
+    let output = check(tmp);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("Error: could not read {}: ", tmp.display())), "{}", stderr);
+    assert!(!stderr.contains("is not up to date"));
+    // a missing file
+    let missing = tmp.join("missing.rs");
+    let output = check(&missing);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("{} is not up to date", missing.display())));
+    assert!(!missing.exists());
+}
@@ -0,0 +1,65 @@
+//! Helpers shared by the tests running blexicon.
+
+// each test uses some of them
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Lexicons with values blexicon warns about, which the fixtures must not have.
+pub fn invalid() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/invalid")
+}
+
+/// Run blexicon over the sources, returning the generated code.
+pub fn generate(args: &[&str], sources: &[PathBuf]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_blexicon"))
+        .arg("--no-format")
+        .args(args)
+        .args(sources)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "blexicon {:?} failed:\n{}",
+        args,
+        stderr
+    );
+    assert!(
+        !stderr.contains("Warning"),
+        "blexicon {:?} warned:\n{}",
+        args,
+        stderr
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // the code is printed after the names of the files read
+    stdout
+        .lines()
+        .filter(|line| !line.starts_with("Reading "))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// The serde attributes of a field of the code, generated unformatted.
+pub fn field_attrs<'a>(code: &'a str, field: &str) -> Vec<&'a str> {
+    let lines: Vec<&str> = code.lines().collect();
+    let declaration = format!("pub {}:", field);
+    let Some(i) = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with(&declaration))
+    else {
+        panic!("no field {} in:\n{}", field, code);
+    };
+    lines[..i]
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with("#[") || line.starts_with("//"))
+        .filter(|line| line.starts_with("#[serde"))
+        .collect()
+}
@@ -0,0 +1,717 @@
+//! Check that the generated code compiles, by building a crate out of the
+//! code generated for the fixtures with various options, and that records
+//! go through it unchanged, by running the tests of `ROUNDTRIP` there.
+//!
+//! The fixtures are a few atproto lexicons in `fixtures/app` and
+//! `fixtures/com/atproto`, along with edge cases in `fixtures/test` and
+//! `fixtures/com/example`: keywords as names, nested objects, refs between
+//! files, unions, recursive refs, constants, defaults, tokens, nulls, scalar
+//! defs, outputs of each shape...
+
+mod common;
+
+use common::{fixtures, generate};
+use std::path::Path;
+use std::process::Command;
+
+const MANIFEST: &str = r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "{edition}"
+
+[dependencies]
+serde = { version = "1.0", features = ["derive"] }
+serde_json = "1.0"
+unicode-segmentation = "1"
+chrono = { version = "0.4", features = ["serde"] }
+erased-serde = "0.4"
+
+[features]
+default = ["lexicons"]
+lexicons = []
+
+[workspace]
+"#;
+
+/// Tests of the generated `no_std` crate
+const NO_STD: &str = r#"
+#[cfg(test)]
+mod no_std {
+    use crate::modules::app::bsky::feed::post::{Datetime, Post};
+
+    #[test]
+    fn build_and_roundtrip() {
+        let created_at: Datetime = "2024-01-01T00:00:00.000Z".parse().unwrap();
+        let post = Post::builder()
+            .text("hello")
+            .createdAt(created_at)
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&post).unwrap();
+        let back: Post = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.text, "hello");
+        let record = crate::consolidated::deserialize_record("app.bsky.feed.post", json.as_bytes());
+        assert!(record.is_ok());
+    }
+}
+"#;
+
+/// Tests of the generated crate, with the variants as modules
+const ROUNDTRIP: &str = r##"
+#[cfg(test)]
+mod roundtrip {
+    /// A post as stored in a repo, camelCase keys and all
+    const POST: &str = r#"{
+        "$type": "app.bsky.feed.post",
+        "text": "hello #atproto",
+        "createdAt": "2024-01-01T00:00:00.000Z",
+        "langs": ["en"],
+        "facets": [{
+            "index": { "byteStart": 6, "byteEnd": 14 },
+            "features": [{ "$type": "app.bsky.richtext.facet#tag", "tag": "atproto" }]
+        }],
+        "reply": {
+            "root": {
+                "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc22",
+                "cid": "bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi"
+            },
+            "parent": {
+                "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc22",
+                "cid": "bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi"
+            }
+        }
+    }"#;
+
+    /// Deserialize the record and serialize it back, which must give the
+    /// same JSON, but for the `$type` of the record itself unless `typed`
+    fn roundtrip<T: serde::Serialize + serde::de::DeserializeOwned>(json: &str, typed: bool) {
+        let mut value: serde_json::Value = serde_json::from_str(json).unwrap();
+        if !typed {
+            value.as_object_mut().unwrap().remove("$type");
+        }
+        let record: T = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&record).unwrap(), value);
+    }
+
+    #[test]
+    fn post() {
+        roundtrip::<crate::flat::Post>(POST, false);
+    }
+
+    #[test]
+    fn camel_case_post() {
+        roundtrip::<crate::camel_case::app::bsky::feed::post::Post>(POST, false);
+    }
+
+    #[test]
+    fn registry() {
+        let record = crate::modules::deserialize_record("app.bsky.feed.post", POST.as_bytes());
+        let mut value: serde_json::Value = serde_json::from_str(POST).unwrap();
+        value.as_object_mut().unwrap().remove("$type");
+        assert_eq!(serde_json::to_value(record.unwrap()).unwrap(), value);
+        assert!(crate::flat::RECORD_NSIDS.contains(&"app.bsky.feed.post"));
+        assert!(matches!(
+            crate::flat::deserialize_record("app.bsky.feed.nope", b"{}"),
+            Err(crate::flat::RecordError::UnknownNsid(_))
+        ));
+    }
+
+    #[test]
+    fn flat_names() {
+        use crate::flat::{ComExampleGetObjectThing, ComExampleNulThing, GetUnionOutput, NulU};
+
+        let output: GetUnionOutput =
+            serde_json::from_str(r#"{"$type": "com.example.getObject#thing", "name": "x"}"#).unwrap();
+        let thing: &ComExampleGetObjectThing = output.as_thing().unwrap();
+        assert_eq!(thing.name, "x");
+        let u: NulU = serde_json::from_str(r#"{"$type": "com.example.nul#thing"}"#).unwrap();
+        assert!(matches!(u, NulU::Thing(ComExampleNulThing {})));
+    }
+
+    #[test]
+    fn non_empty_string() {
+        use crate::derives::test::strs::Strs;
+
+        let strs: Strs = serde_json::from_str(r#"{"text": "hi"}"#).unwrap();
+        assert_eq!(strs.text.as_str(), "hi");
+        assert!(serde_json::from_str::<Strs>(r#"{"text": ""}"#).is_err());
+    }
+
+    #[test]
+    fn grapheme_limits() {
+        use crate::modules::test::strs::{Constraint, Strs};
+
+        let validate = |text: String| {
+            let strs: Strs = serde_json::from_value(serde_json::json!({ "text": text })).unwrap();
+            strs.validate().map_err(|e| e.constraint)
+        };
+        // a family is a single grapheme of 7 codepoints, 25 bytes
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}";
+        assert_eq!(family.len(), 25);
+        assert_eq!(validate(family.repeat(3)), Ok(()));
+        // 100 bytes are well within the 3000 bytes, but not 4 graphemes
+        assert_eq!(validate(family.repeat(4)), Err(Constraint::MaxGraphemes(3)));
+        // and 2 graphemes of 1001 codepoints are within 3 graphemes, but
+        // not within 3000 bytes
+        let accented = format!("e{}", "\u{301}".repeat(1000));
+        assert_eq!(validate(accented.repeat(2)), Err(Constraint::MaxLength(3000)));
+    }
+
+    #[test]
+    fn cid_links() {
+        use crate::modules::test::cid::Cid;
+
+        let cid = "bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi";
+        let link = serde_json::json!({ "c": { "$link": cid } });
+        roundtrip::<Cid>(&link.to_string(), false);
+        // a plain CID string is taken too, and written out as a link
+        let plain: Cid = serde_json::from_value(serde_json::json!({ "c": cid })).unwrap();
+        assert_eq!(plain.c.0, cid);
+        assert_eq!(serde_json::to_value(&plain).unwrap(), link);
+    }
+
+    #[test]
+    fn bytes() {
+        use crate::modules::test::bin::Bin;
+
+        // the standard alphabet, unpadded, for each length modulo 3
+        for (b64, bytes) in [("aGkh", &b"hi!"[..]), ("+/8", &[0xfb, 0xff]), ("AA", &[0])] {
+            let json = serde_json::json!({ "b": { "$bytes": b64 } }).to_string();
+            let bin: Bin = serde_json::from_str(&json).unwrap();
+            assert_eq!(bin.b.0, bytes);
+            roundtrip::<Bin>(&json, false);
+        }
+        // padding is accepted, but not written out
+        let bin: Bin = serde_json::from_str(r#"{"b": {"$bytes": "AA=="}}"#).unwrap();
+        assert_eq!(serde_json::to_string(&bin).unwrap(), r#"{"b":{"$bytes":"AA"}}"#);
+        assert!(serde_json::from_str::<Bin>(r#"{"b": {"$bytes": "A*"}}"#).is_err());
+    }
+
+    #[test]
+    fn subscription() {
+        use crate::modules::com::atproto::sync::subscribe_repos::{
+            SubscribeReposError, SubscribeReposMessage, SubscribeReposParams,
+        };
+
+        let params: SubscribeReposParams = serde_json::from_str(r#"{"cursor": 42}"#).unwrap();
+        assert_eq!(params.cursor, Some(42));
+        roundtrip::<SubscribeReposParams>("{}", false);
+        let identity = r#"{
+            "$type": "com.atproto.sync.subscribeRepos#identity",
+            "seq": 1234,
+            "did": "did:plc:abc",
+            "time": "2024-01-01T00:00:00.000Z",
+            "handle": "alice.test"
+        }"#;
+        roundtrip::<SubscribeReposMessage>(identity, true);
+        let message = SubscribeReposMessage::try_from(serde_json::from_str::<serde_json::Value>(identity).unwrap());
+        match message.unwrap() {
+            SubscribeReposMessage::Identity(identity) => assert_eq!(identity.seq, 1234),
+            other => panic!("not an identity: {:?}", other),
+        }
+        // a message of a later revision of the lexicon is kept as is
+        let later = r#"{"$type": "com.atproto.sync.subscribeRepos#sync", "seq": 1235}"#;
+        roundtrip::<SubscribeReposMessage>(later, true);
+        assert!(matches!(serde_json::from_str(later).unwrap(), SubscribeReposMessage::Unknown(_)));
+        // the errors are named as in the lexicon
+        let error: SubscribeReposError = serde_json::from_str(r#""ConsumerTooSlow""#).unwrap();
+        assert!(matches!(error, SubscribeReposError::ConsumerTooSlow));
+        assert!(serde_json::from_str::<SubscribeReposError>(r#""NotAnError""#).is_err());
+    }
+
+    #[test]
+    fn array_items() {
+        use crate::modules::app::bsky::embed::images::Images;
+        use crate::modules::test::arrays::{Arrays, ArraysFeaturesItem};
+
+        let image = serde_json::json!({
+            "image": {
+                "$type": "blob",
+                "ref": { "$link": "bafkreiakd3oyfnpim3qdrqdhbwqj2ri4ftxtfxkgq2oi5zp5a3ie2pqwdm" },
+                "mimeType": "image/jpeg",
+                "size": 123456
+            },
+            "alt": "a cat",
+            "aspectRatio": { "width": 4, "height": 3 }
+        });
+        let embed = serde_json::json!({ "images": [image, { "image": image["image"], "alt": "" }] });
+        roundtrip::<Images>(&embed.to_string(), false);
+        let images: Images = serde_json::from_value(embed).unwrap();
+        assert_eq!(images.images[0].aspectRatio.as_ref().unwrap().width, 4);
+        assert_eq!(images.validate(), Ok(()));
+        assert_eq!(images.images[1].validate(), Ok(()));
+        // arrays of inline objects and of unions, with a type for their items
+        let arrays = r#"{
+            "images": [{ "alt": "a cat" }],
+            "features": [{ "$type": "test.arrays#a", "x": 1 }, { "$type": "test.arrays#b" }]
+        }"#;
+        roundtrip::<Arrays>(arrays, false);
+        let arrays: Arrays = serde_json::from_str(arrays).unwrap();
+        assert_eq!(arrays.images[0].alt, "a cat");
+        let features = arrays.features.unwrap();
+        assert_eq!(features[0].as_a().unwrap().x, Some(1));
+        assert!(matches!(features[1], ArraysFeaturesItem::Unknown(_)));
+    }
+
+    #[test]
+    fn unknown_fields() {
+        use crate::modules::test::unknown::Unknown;
+
+        let record = r#"{
+            "$type": "test.unknown",
+            "payload": {
+                "$type": "com.example.whatever",
+                "nested": { "list": [1, "two", { "three": null }], "flag": true }
+            },
+            "embed": { "$type": "com.example.later#embed", "uri": "at://did:plc:abc" }
+        }"#;
+        roundtrip::<Unknown>(record, false);
+        let record: Unknown = serde_json::from_str(record).unwrap();
+        // held whole, nested objects and all
+        assert_eq!(record.payload["nested"]["list"][2]["three"], serde_json::Value::Null);
+        assert_eq!(record.payload["nested"]["flag"], true);
+        // and so is a variant of an open union that was not known
+        let embed = record.embed.unwrap();
+        assert!(embed.as_thing().is_none());
+        assert_eq!(embed.as_unknown().unwrap()["uri"], "at://did:plc:abc");
+    }
+
+    #[test]
+    fn default_impl() {
+        use crate::consolidated::app::bsky::feed::post::Post;
+        use crate::consolidated::test::consts::Consts;
+        use crate::consolidated::test::defaults::{Defaults, DefaultsPlatform};
+
+        let post = Post { text: "hello".to_string(), ..Default::default() };
+        assert_eq!(post.validate(), Ok(()));
+        assert_eq!(
+            serde_json::to_value(&post).unwrap(),
+            serde_json::json!({ "text": "hello", "createdAt": "1970-01-01T00:00:00Z" })
+        );
+        // the fields set over the defaults are still checked by validate()
+        let post = Post { text: "x".repeat(3001), ..Default::default() };
+        assert_eq!(post.validate().unwrap_err().field, "text");
+        // the schema defaults and consts are filled in
+        let defaults = Defaults::default();
+        assert_eq!((defaults.limit, defaults.flag, defaults.opt), (50, true, None));
+        assert!(matches!(defaults.platform, DefaultsPlatform::Web));
+        assert_eq!((defaults.did.0.as_str(), defaults.s.as_str()), ("did:web:x", "he\"llo"));
+        let consts = Consts::default();
+        assert_eq!((consts.kind.as_str(), consts.n), ("fixed", 3));
+        assert_eq!(consts.validate(), Ok(()));
+        // and are the same as those filled in when deserializing
+        let defaults: serde_json::Value = serde_json::to_value(Defaults::default()).unwrap();
+        let deserialized: Defaults = serde_json::from_str("{}").unwrap();
+        assert_eq!(serde_json::to_value(deserialized).unwrap(), defaults);
+    }
+
+    #[test]
+    fn chrono_datetimes() {
+        use crate::consolidated::com::example::dt::Dt;
+
+        let dt = |created_at: &str| {
+            serde_json::from_value::<Dt>(serde_json::json!({ "createdAt": created_at, "list": [] }))
+        };
+        let instant = chrono::DateTime::parse_from_rfc3339("2024-01-01T12:34:56.789Z").unwrap();
+        // each timestamp, and how it is written out: in UTC, with the
+        // fractional seconds only when there are some
+        let timestamps = [
+            ("2024-01-01T12:34:56Z", "2024-01-01T12:34:56Z"),
+            ("2024-01-01T12:34:56.000Z", "2024-01-01T12:34:56Z"),
+            ("2024-01-01T12:34:56.789Z", "2024-01-01T12:34:56.789Z"),
+            ("2024-01-01T12:34:56.123456Z", "2024-01-01T12:34:56.123456Z"),
+            ("2024-01-01T14:34:56.789+02:00", "2024-01-01T12:34:56.789Z"),
+            ("2024-01-01T07:04:56.789-05:30", "2024-01-01T12:34:56.789Z"),
+        ];
+        for (timestamp, written) in timestamps {
+            let record = dt(timestamp).unwrap();
+            assert_eq!(serde_json::to_value(&record).unwrap()["createdAt"], written, "{}", timestamp);
+            // and back again, to the same instant
+            let again = dt(written).unwrap();
+            assert_eq!(again.createdAt, record.createdAt, "{}", timestamp);
+        }
+        assert_eq!(dt("2024-01-01T14:34:56.789+02:00").unwrap().createdAt, instant);
+        // a datetime needs a time and an offset
+        assert!(dt("2024-01-01").is_err());
+        assert!(dt("2024-01-01T12:34:56").is_err());
+        // as optional fields too
+        let record: Dt = serde_json::from_value(serde_json::json!({
+            "createdAt": "2024-01-01T12:34:56Z",
+            "editedAt": "2024-01-01T14:34:56+02:00",
+            "deletedAt": null,
+            "list": []
+        }))
+        .unwrap();
+        assert_eq!(record.editedAt, Some(record.createdAt));
+        assert_eq!(serde_json::to_value(&record).unwrap()["editedAt"], "2024-01-01T12:34:56Z");
+        assert_eq!(record.deletedAt, None);
+    }
+
+    #[test]
+    fn tokens() {
+        use crate::modules::app::bsky::graph::defs::{curatelist, listPurpose, modlist, referencelist};
+        use crate::modules::com::example::tok::{other, Tok};
+
+        // the full NSID of each token, as among the known values
+        assert_eq!(modlist::TOKEN, "app.bsky.graph.defs#modlist");
+        assert_eq!(curatelist::TOKEN, "app.bsky.graph.defs#curatelist");
+        assert_eq!(referencelist::TOKEN, "app.bsky.graph.defs#referencelist");
+        let purpose: listPurpose = serde_json::from_value(modlist::TOKEN.into()).unwrap();
+        assert!(matches!(purpose, listPurpose::AppBskyGraphDefsModlist));
+        assert_eq!(serde_json::to_value(listPurpose::AppBskyGraphDefsCuratelist).unwrap(), curatelist::TOKEN);
+        // a `main` token is the bare NSID
+        assert_eq!(Tok::TOKEN, "com.example.tok");
+        assert_eq!(other::TOKEN, "com.example.tok#other");
+    }
+
+    #[test]
+    fn consts() {
+        use crate::modules::test::consts::{Consts, Constraint, ValidationError};
+
+        // the consts are filled in when absent
+        let consts: Consts = serde_json::from_str("{}").unwrap();
+        assert_eq!(consts.kind, "fixed");
+        assert_eq!(consts.validate(), Ok(()));
+        let other = |json: &str| serde_json::from_str::<Consts>(json).unwrap().validate().unwrap_err();
+        assert_eq!(
+            other(r#"{"kind": "other"}"#),
+            ValidationError { field: "kind", constraint: Constraint::Const("fixed") }
+        );
+        assert_eq!(other(r#"{"n": 4}"#).constraint, Constraint::Const("3"));
+        // consts among the knownValues and the enum of a string
+        assert_eq!(other(r#"{"mode": "a"}"#).constraint, Constraint::Const("b"));
+        assert_eq!(other(r#"{"mode": "c"}"#).constraint, Constraint::Const("b"));
+        assert_eq!(other(r#"{"level": "low"}"#).constraint, Constraint::Const("high"));
+    }
+
+    #[test]
+    fn format_newtypes() {
+        use crate::derives::com::atproto::repo::strong_ref::AtUri;
+        use crate::modules::com::atproto::repo::strong_ref::AtUri as UncheckedAtUri;
+
+        let uri = "at://did:plc:abc/app.bsky.feed.post/3kabc22";
+        assert_eq!(uri.parse::<AtUri>().unwrap().to_string(), uri);
+        let e = "did:plc:abc".parse::<AtUri>().unwrap_err();
+        assert_eq!(e.to_string(), "\"did:plc:abc\" is not a valid at-uri");
+        // without --strict-newtypes, anything goes
+        assert_eq!("did:plc:abc".parse::<UncheckedAtUri>().unwrap().0, "did:plc:abc");
+    }
+
+    #[test]
+    fn union_variants() {
+        use crate::modules::app::bsky::richtext::facet::{tag, FacetFeaturesItem};
+
+        let post: crate::flat::Post = serde_json::from_str(POST).unwrap();
+        let feature = &post.facets.unwrap()[0].features[0];
+        assert_eq!(feature.as_tag().unwrap().tag, "atproto");
+        assert!(feature.as_mention().is_none());
+        let feature = FacetFeaturesItem::from(tag { tag: "rust".to_string() });
+        assert_eq!(feature.into_tag().unwrap().tag, "rust");
+    }
+
+    #[test]
+    fn recursive_thread() {
+        let thread = r#"{
+            "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc23",
+            "parent": {
+                "$type": "test.thread",
+                "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc22",
+                "parent": { "$type": "test.thread#notFound", "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc21" }
+            },
+            "replies": [{ "$type": "test.thread", "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc24" }],
+            "quote": { "next": { "thread": { "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc25" } } }
+        }"#;
+        roundtrip::<crate::modules::test::thread::Thread>(thread, false);
+    }
+
+    #[test]
+    fn query_params() {
+        use crate::modules::test::params::ParamsParams;
+
+        let params: ParamsParams = serde_json::from_str(
+            r#"{"repo": "did:plc:abc", "collections": ["app.bsky.feed.post", "app.bsky.feed.like"], "reverse": true}"#,
+        )
+        .unwrap();
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        // an array is a key per item, absent options are skipped
+        assert_eq!(
+            params.to_query(),
+            [
+                pair("repo", "did:plc:abc"),
+                pair("collections", "app.bsky.feed.post"),
+                pair("collections", "app.bsky.feed.like"),
+                pair("limit", "50"),
+                pair("reverse", "true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn atrium_post() {
+        use crate::atrium::app::bsky::feed::post::{Record, RecordEmbedRefs};
+
+        roundtrip::<Record>(POST, false);
+        let _: Option<RecordEmbedRefs> = None;
+        assert_eq!(crate::atrium::app::bsky::feed::get_timeline::NSID, "app.bsky.feed.getTimeline");
+    }
+
+    #[test]
+    fn output_schemas() {
+        use crate::modules::com::example::{get_ref::GetRefOutput, get_union::GetUnionOutput};
+
+        let strong_ref = r#"{
+            "uri": "at://did:plc:abc/app.bsky.feed.post/3kabc22",
+            "cid": "bafyreidwaivazkwu67xztlmuobx35hs2lnfh3kolmgfmucldvhd3sgzcqi"
+        }"#;
+        roundtrip::<GetRefOutput>(strong_ref, false);
+        let output: GetUnionOutput =
+            serde_json::from_str(r#"{"$type": "com.example.getObject#thing", "name": "x"}"#).unwrap();
+        assert_eq!(output.as_thing().unwrap().name, "x");
+    }
+
+    #[test]
+    fn build_post() {
+        use crate::derives::app::bsky::feed::post::{Datetime, Post};
+        use crate::derives::app::bsky::feed::post::{Constraint, ValidationError};
+
+        let created_at: Datetime = "2024-01-01T00:00:00.000Z".parse().unwrap();
+        let post = Post::builder()
+            .text("hello")
+            .createdAt(created_at.clone())
+            .build()
+            .unwrap();
+        assert_eq!(post.text, "hello");
+        assert_eq!(post.langs, None);
+        let missing = Post::builder().text("hello").build().unwrap_err();
+        assert_eq!(
+            missing,
+            ValidationError { field: "createdAt", constraint: Constraint::Required }
+        );
+        let too_long = Post::builder().text("x".repeat(301)).createdAt(created_at).build();
+        assert_eq!(too_long.unwrap_err().field, "text");
+    }
+
+    #[test]
+    fn lexicon_consts() {
+        use crate::modules::app::bsky::feed::post::{LEXICON_HASH, LEXICON_REVISION};
+
+        assert_eq!(LEXICON_REVISION, None);
+        assert_eq!(LEXICON_HASH.len(), 64);
+        assert_ne!(LEXICON_HASH, crate::modules::app::bsky::feed::get_timeline::LEXICON_HASH);
+    }
+
+    #[test]
+    fn typed_post() {
+        roundtrip::<crate::typed::app::bsky::feed::post::Post>(POST, true);
+    }
+}
+"##;
+
+#[test]
+fn generated_code_compiles() {
+    let fixtures = fixtures();
+    let all = [fixtures.clone()];
+    // all the definitions of a flat output share a scope, where those
+    // whose names clash, such as the `#thing` defs, get their NSID
+    let variants: Vec<(&str, String)> = vec![
+        ("flat", generate(&["--record-registry"], &all)),
+        (
+            "modules",
+            generate(&["--modules", "--record-registry"], &all),
+        ),
+        (
+            "consolidated",
+            generate(
+                &[
+                    "--consolidate",
+                    "--client",
+                    "--derive-default-impl",
+                    "--chrono",
+                    "--builders",
+                ],
+                &all,
+            ),
+        ),
+        (
+            "derives",
+            generate(
+                &[
+                    "--modules",
+                    "--derive",
+                    "PartialEq,Eq",
+                    "--unsigned",
+                    "--strict-newtypes",
+                    "--builders",
+                ],
+                &all,
+            ),
+        ),
+        (
+            "wrapped",
+            generate(
+                &[
+                    "--consolidate",
+                    "--wrap-module",
+                    "lexicons",
+                    "--feature",
+                    "lexicons",
+                ],
+                &all,
+            ),
+        ),
+        (
+            "camel_case",
+            generate(&["--modules", "--rename-all", "camelCase"], &all),
+        ),
+        (
+            "atrium",
+            generate(
+                &["--style", "atrium", "--client", "--record-registry"],
+                &all,
+            ),
+        ),
+        (
+            "namespace",
+            generate(&["--modules", "--namespace", "app.bsky.feed"], &all),
+        ),
+        (
+            "typed",
+            generate(
+                &["--modules", "--with-type-field", "--derive-default-impl"],
+                &all,
+            ),
+        ),
+    ];
+
+    test_crate("generated", "2021", "", &variants, ROUNDTRIP);
+}
+
+#[test]
+fn generated_code_compiles_in_2018() {
+    let all = [fixtures()];
+    let args = [
+        "--consolidate",
+        "--client",
+        "--strict-newtypes",
+        "--edition",
+        "2018",
+    ];
+    test_crate(
+        "generated2018",
+        "2018",
+        "",
+        &[("code", generate(&args, &all))],
+        "",
+    );
+}
+
+/// Without `std`, any path to it is an error, as the crate does not know it
+#[test]
+fn generated_code_compiles_without_std() {
+    let all = [fixtures()];
+    let variants = [
+        (
+            "modules",
+            generate(
+                &[
+                    "--modules",
+                    "--no-std",
+                    "--client",
+                    "--record-registry",
+                    "--builders",
+                    "--strict-newtypes",
+                ],
+                &all,
+            ),
+        ),
+        (
+            "consolidated",
+            generate(
+                &[
+                    "--consolidate",
+                    "--no-std",
+                    "--chrono",
+                    "--derive-default-impl",
+                    "--record-registry",
+                ],
+                &all,
+            ),
+        ),
+    ];
+    test_crate(
+        "generated_no_std",
+        "2021",
+        "#![no_std]\nextern crate alloc;\n",
+        &variants,
+        NO_STD,
+    );
+}
+
+#[test]
+fn generated_dir_compiles() {
+    use blexicon::codegen::{codegen_dir, CodegenOptions};
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("generated_dir");
+    let output = dir.join("src/lexicons");
+    let _ = std::fs::remove_dir_all(&output);
+    let opts = CodegenOptions {
+        common: true,
+        ..Default::default()
+    };
+    codegen_dir(&fixtures(), &output, &opts).unwrap();
+    // generating again leaves the files alone
+    let modified = || {
+        std::fs::metadata(output.join("mod.rs"))
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+    let before = modified();
+    codegen_dir(&fixtures(), &output, &opts).unwrap();
+    assert_eq!(modified(), before);
+    assert!(output.join("app/bsky/feed/post.rs").is_file());
+    test_crate("generated_dir", "2021", "", &[], "pub mod lexicons;\n");
+}
+
+/// Make a crate of the edition with the `root` attributes and items, a
+/// module per variant, followed by the `extra` code, and run its tests. The
+/// crates share a target directory, so their dependencies are only built
+/// once.
+fn test_crate(
+    name: &str,
+    edition: &str,
+    root: &str,
+    variants: &[(&str, String)],
+    extra: &str,
+) {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let dir = tmp.join(name);
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    let manifest = MANIFEST
+        .replace("{name}", name)
+        .replace("{edition}", edition);
+    std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+    let mut lib = String::from("#![allow(non_camel_case_types, non_snake_case, dead_code)]\n");
+    lib.push_str(root);
+    for (name, code) in variants {
+        std::fs::write(dir.join("src").join(format!("{}.rs", name)), code).unwrap();
+        lib.push_str(&format!("pub mod {};\n", name));
+    }
+    lib.push_str(extra);
+    std::fs::write(dir.join("src/lib.rs"), lib).unwrap();
+
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = Command::new(cargo)
+        .args(["test", "--quiet", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .env("CARGO_TARGET_DIR", tmp.join("target"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "the generated code in {} does not compile or pass its tests:\n{}{}",
+        dir.display(),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,66 @@
+//! Resolving refs between the two lexicons of `fixtures/test/resolve`,
+//! which refer to each other.
+
+mod common;
+
+use blexicon::{LexiconResolver, ResolveError};
+use common::fixtures;
+
+fn resolver() -> LexiconResolver {
+    LexiconResolver::load_dir(&fixtures().join("test/resolve")).unwrap()
+}
+
+/// The description of the def the ref from `current` points to
+fn resolved<'a>(resolver: &'a LexiconResolver, reference: &str, current: &str) -> &'a str {
+    resolver
+        .resolve(reference, current)
+        .and_then(|def| def.description.as_deref())
+        .unwrap_or_else(|| panic!("{} from {} does not resolve", reference, current))
+}
+
+#[test]
+fn refs_resolve() {
+    let resolver = resolver();
+    let one = "test.resolve.one";
+    let two = "test.resolve.two";
+    // in the same file
+    assert_eq!(resolved(&resolver, "#main", two), "two main");
+    assert_eq!(resolved(&resolver, "#note", one), "one note");
+    // across the files, both ways
+    assert_eq!(
+        resolved(&resolver, "test.resolve.two#detail", one),
+        "two detail"
+    );
+    assert_eq!(
+        resolved(&resolver, "test.resolve.one#note", two),
+        "one note"
+    );
+    // a bare NSID is its main def, whatever the current file
+    assert_eq!(resolved(&resolver, "test.resolve.two", one), "two main");
+    assert_eq!(
+        resolved(&resolver, "test.resolve.one#main", one),
+        "one main"
+    );
+}
+
+#[test]
+fn dangling_refs() {
+    let resolver = resolver();
+    match resolver.try_resolve("#missing", "test.resolve.one") {
+        Err(ResolveError::MissingDef { nsid, name, .. }) => {
+            assert_eq!(
+                (nsid.as_str(), name.as_str()),
+                ("test.resolve.one", "missing")
+            )
+        }
+        other => panic!("expected a missing def, got {:?}", other),
+    }
+    match resolver.try_resolve("test.resolve.three#note", "test.resolve.one") {
+        Err(ResolveError::MissingLexicon { nsid, .. }) => assert_eq!(nsid, "test.resolve.three"),
+        other => panic!("expected a missing lexicon, got {:?}", other),
+    }
+    // rather than falling back to a def of the current file
+    assert!(resolver
+        .resolve("test.resolve.three#note", "test.resolve.one")
+        .is_none());
+}
@@ -0,0 +1,98 @@
+//! Decoding of CARv1 files, as carried in the `blocks` of a commit.
+//!
+//! A CAR file is a varint-length-prefixed DAG-CBOR header, followed by
+//! blocks, each being a varint length, then the binary CID and the data.
+
+use crate::cid::Cid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub enum CarError {
+    /// The data ended in the middle of something
+    Truncated,
+    /// A varint is longer than 64 bits
+    BadVarint,
+    /// The header could not be decoded, or has an unknown version
+    BadHeader(String),
+    /// A block does not start with a valid CID
+    BadCid,
+}
+
+impl std::fmt::Display for CarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CarError::Truncated => write!(f, "CAR data is truncated"),
+            CarError::BadVarint => write!(f, "invalid varint in CAR data"),
+            CarError::BadHeader(e) => write!(f, "invalid CAR header: {}", e),
+            CarError::BadCid => write!(f, "invalid CID in CAR block"),
+        }
+    }
+}
+
+impl std::error::Error for CarError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CarHeader {
+    version: u64,
+    roots: Vec<Cid>,
+}
+
+/// Read an unsigned LEB128 varint, returning it and the bytes consumed
+pub(crate) fn read_varint(data: &[u8]) -> Result<(u64, usize), CarError> {
+    let mut value: u64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        if i >= 10 {
+            return Err(CarError::BadVarint);
+        }
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(CarError::Truncated)
+}
+
+/// The contents of a CARv1 file
+#[derive(Debug, Clone)]
+pub struct Car {
+    /// The CIDs the file is about, for a commit just the commit object
+    pub roots: Vec<Cid>,
+    /// The blocks with their CIDs, in the order of the file
+    pub blocks: Vec<(Cid, Vec<u8>)>,
+}
+
+/// Split a CARv1 file into its blocks, keyed by CID
+pub fn decode_car(blocks: &[u8]) -> Result<HashMap<Cid, Vec<u8>>, CarError> {
+    Ok(read_car(blocks)?.blocks.into_iter().collect())
+}
+
+/// Read a CARv1 file, keeping the roots and the order of the blocks
+pub fn read_car(blocks: &[u8]) -> Result<Car, CarError> {
+    let (header_len, n) = read_varint(blocks)?;
+    let header_end = n
+        .checked_add(header_len as usize)
+        .ok_or(CarError::Truncated)?;
+    let header_data = blocks.get(n..header_end).ok_or(CarError::Truncated)?;
+    let header: CarHeader =
+        serde_cbor::from_slice(header_data).map_err(|e| CarError::BadHeader(e.to_string()))?;
+    if header.version != 1 {
+        return Err(CarError::BadHeader(format!("version {}", header.version)));
+    }
+
+    let mut car = Car {
+        roots: header.roots,
+        blocks: vec![],
+    };
+    let mut pos = header_end;
+    while pos < blocks.len() {
+        let (len, n) = read_varint(&blocks[pos..])?;
+        pos += n;
+        let end = pos.checked_add(len as usize).ok_or(CarError::Truncated)?;
+        let block = blocks.get(pos..end).ok_or(CarError::Truncated)?;
+        let (cid, cid_len) = Cid::read(block).ok_or(CarError::BadCid)?;
+        car.blocks.push((cid, block[cid_len..].to_vec()));
+        pos = end;
+    }
+    Ok(car)
+}
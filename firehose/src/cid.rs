@@ -0,0 +1,266 @@
+//! Content identifiers, the links between DAG-CBOR blocks.
+//!
+//! In DAG-CBOR a link is a byte string with tag 42, holding the binary CID
+//! behind a zero byte (the "identity" multibase prefix). In JSON it is the
+//! usual string form, base32 for CIDv1 and base58btc for CIDv0.
+
+use crate::car::read_varint;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The CBOR tag for links
+pub const CID_TAG: u64 = 42;
+
+/// The multicodec of DAG-CBOR blocks, such as records
+pub const DAG_CBOR: u64 = 0x71;
+
+/// The multihash code of sha2-256
+pub const SHA2_256: u64 = 0x12;
+
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// A CID, kept in its binary form
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cid(Vec<u8>);
+
+impl Cid {
+    /// Read the binary CID at the start of the data, returning it and its length
+    pub fn read(data: &[u8]) -> Option<(Cid, usize)> {
+        // CIDv0 is a bare sha2-256 multihash
+        if data.len() >= 2 && data[0] == 0x12 && data[1] == 0x20 {
+            return data.get(..34).map(|cid| (Cid(cid.to_vec()), 34));
+        }
+        let (version, mut pos) = read_varint(data).ok()?;
+        if version != 1 {
+            return None;
+        }
+        // codec, then the multihash code and digest size
+        for _ in 0..2 {
+            let (_, n) = read_varint(&data[pos..]).ok()?;
+            pos += n;
+        }
+        let (digest_len, n) = read_varint(&data[pos..]).ok()?;
+        let end = (pos + n).checked_add(digest_len as usize)?;
+        data.get(..end).map(|cid| (Cid(cid.to_vec()), end))
+    }
+
+    /// The CID in the data, which must hold nothing else
+    pub fn from_bytes(data: &[u8]) -> Option<Cid> {
+        match Cid::read(data) {
+            Some((cid, len)) if len == data.len() => Some(cid),
+            _ => None,
+        }
+    }
+
+    /// The binary form
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The version, codec, multihash code and digest
+    fn parts(&self) -> (u64, u64, u64, &[u8]) {
+        if self.0[0] == 0x12 {
+            return (0, 0x70, 0x12, &self.0[2..]);
+        }
+        let mut values = [0; 4];
+        let mut pos = 0;
+        for value in values.iter_mut() {
+            // validated when the CID was read
+            let (v, n) = read_varint(&self.0[pos..]).unwrap();
+            *value = v;
+            pos += n;
+        }
+        (values[0], values[1], values[2], &self.0[pos..])
+    }
+
+    pub fn version(&self) -> u64 {
+        self.parts().0
+    }
+
+    /// The multicodec of the data, e.g. 0x71 for DAG-CBOR, 0x55 for raw
+    pub fn codec(&self) -> u64 {
+        self.parts().1
+    }
+
+    /// The multihash function, e.g. 0x12 for sha2-256
+    pub fn hash_code(&self) -> u64 {
+        self.parts().2
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        self.parts().3
+    }
+}
+
+/// The CIDv1 of a DAG-CBOR block, such as a record, with its sha2-256. A
+/// block that does not hash to the CID it is linked with has been altered,
+/// or was not encoded back the way it was decoded.
+pub fn record_cid(block: &[u8]) -> Cid {
+    use sha2::{Digest, Sha256};
+
+    let digest = Sha256::digest(block);
+    let mut cid = vec![1, DAG_CBOR as u8, SHA2_256 as u8, digest.len() as u8];
+    cid.extend_from_slice(&digest);
+    Cid(cid)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_lowercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn base58_encode(data: &[u8]) -> String {
+    // little endian base 58 digits
+    let mut digits: Vec<u8> = vec![];
+    for byte in data {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = data.iter().take_while(|&&b| b == 0).count();
+    let mut out = "1".repeat(zeros);
+    out.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|&d| BASE58_ALPHABET[d as usize] as char),
+    );
+    out
+}
+
+pub(crate) fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    // little endian bytes
+    let mut bytes: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|&a| a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = s.bytes().take_while(|&c| c == b'1').count();
+    let mut out = vec![0; zeros];
+    out.extend(bytes.iter().rev());
+    Some(out)
+}
+
+impl std::fmt::Display for Cid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.version() == 0 {
+            write!(f, "{}", base58_encode(&self.0))
+        } else {
+            write!(f, "b{}", base32_encode(&self.0))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCidError(pub String);
+
+impl std::fmt::Display for ParseCidError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid CID {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseCidError {}
+
+impl std::str::FromStr for Cid {
+    type Err = ParseCidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = if s.len() == 46 && s.starts_with("Qm") {
+            base58_decode(s)
+        } else {
+            s.strip_prefix('b').and_then(base32_decode)
+        };
+        bytes
+            .and_then(|bytes| Cid::from_bytes(&bytes))
+            .ok_or_else(|| ParseCidError(s.to_string()))
+    }
+}
+
+/// A byte string, as opposed to a sequence of numbers
+struct RawBytes<'a>(&'a [u8]);
+
+impl Serialize for RawBytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Serialize for Cid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            let bytes = [&[0u8][..], &self.0].concat();
+            serde_cbor::tags::Tagged::new(Some(CID_TAG), RawBytes(&bytes)).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Cid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            return s.parse().map_err(D::Error::custom);
+        }
+        let tagged = serde_cbor::tags::Tagged::<serde_bytes::ByteBuf>::deserialize(deserializer)?;
+        if tagged.tag != Some(CID_TAG) {
+            return Err(D::Error::custom(format!(
+                "expected a CID with tag 42, got tag {:?}",
+                tagged.tag
+            )));
+        }
+        match tagged.value.split_first() {
+            Some((0, cid)) => Cid::from_bytes(cid),
+            _ => None,
+        }
+        .ok_or_else(|| D::Error::custom("invalid binary CID"))
+    }
+}
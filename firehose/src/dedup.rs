@@ -0,0 +1,85 @@
+//! Skipping of ops already seen, such as those replayed after reconnecting
+//! with a cursor overlapping the events already processed.
+//!
+//! An op is identified by its repo, path and record CID, so a record that is
+//! changed and changed back is seen again. [`SeenOps`] is the store of the
+//! ops seen, [`LruSeenOps`] keeping the most recent ones in memory.
+
+use crate::{Cid, CommitRecord};
+use std::collections::{HashMap, VecDeque};
+
+/// The identity of an op: repo DID, record path and CID, none for deletes
+pub type OpKey = (String, String, Option<Cid>);
+
+/// A set of the ops seen so far, which can be kept anywhere.
+pub trait SeenOps {
+    /// Record the op as seen, returning whether it was not seen before
+    fn insert(&mut self, key: OpKey) -> bool;
+
+    /// Drop the ops of the commit that were seen before, recording the
+    /// others. Returns whether any op is left.
+    fn retain_new(&mut self, commit: &mut CommitRecord) -> bool {
+        let repo = &commit.repo;
+        commit
+            .ops
+            .retain(|op| self.insert((repo.clone(), op.path.clone(), op.cid.clone())));
+        !commit.ops.is_empty()
+    }
+}
+
+/// The last `capacity` distinct ops seen, in memory. Seeing an op again
+/// makes it the most recent one.
+#[derive(Debug, Clone)]
+pub struct LruSeenOps {
+    capacity: usize,
+    /// The ops, with the stamp of their last sighting
+    seen: HashMap<OpKey, u64>,
+    /// Sightings from the oldest, those with an outdated stamp being stale
+    order: VecDeque<(OpKey, u64)>,
+    stamp: u64,
+}
+
+impl LruSeenOps {
+    pub fn new(capacity: usize) -> Self {
+        LruSeenOps {
+            capacity,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            stamp: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+impl SeenOps for LruSeenOps {
+    fn insert(&mut self, key: OpKey) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        self.stamp += 1;
+        let new = self.seen.insert(key.clone(), self.stamp).is_none();
+        self.order.push_back((key, self.stamp));
+        while self.seen.len() > self.capacity {
+            let Some((oldest, stamp)) = self.order.pop_front() else {
+                break;
+            };
+            if self.seen.get(&oldest) == Some(&stamp) {
+                self.seen.remove(&oldest);
+            }
+        }
+        // ops seen repeatedly leave stale sightings behind
+        if self.order.len() > 2 * self.capacity {
+            let seen = &self.seen;
+            self.order
+                .retain(|(key, stamp)| seen.get(key) == Some(stamp));
+        }
+        new
+    }
+}
@@ -0,0 +1,44 @@
+//! The framing of the messages read from a stream rather than a websocket,
+//! such as stdin or a Unix domain socket.
+//!
+//! Each message is a 4-byte big-endian length, followed by that many bytes
+//! of the message: the same binary frame as a relay sends in a websocket
+//! message, a header and a body in DAG-CBOR. There is nothing before the
+//! first message, and the stream ends after the last one.
+
+use std::io::{Error, ErrorKind, Write};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Read the next message, None at the end of the stream. A message longer
+/// than `max_len` is an error, as is a stream ending within a message.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut len = [0; 4];
+    // a stream that ends right before a length prefix ends cleanly
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..]).await? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        let e = format!("message of {} bytes is too long", len);
+        return Err(Error::new(ErrorKind::InvalidData, e));
+    }
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+/// Write a message with its length prefix
+pub fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> Result<(), Error> {
+    let len = u32::try_from(frame.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "message is too long"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(frame)
+}
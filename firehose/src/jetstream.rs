@@ -0,0 +1,74 @@
+//! Decoding of Jetstream events, which carry the same information as the
+//! firehose as plain JSON, with the records already decoded.
+
+use crate::{AccountEvent, Cid, Error, FirehoseEvent, IdentityEvent};
+use serde::{Deserialize, Serialize};
+
+/// The envelope of every Jetstream event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Envelope {
+    did: String,
+    /// When the event was received by Jetstream, also its cursor
+    time_us: i64,
+    kind: String,
+    commit: Option<Commit>,
+    identity: Option<IdentityEvent>,
+    account: Option<AccountEvent>,
+}
+
+/// A single op of a commit, as delivered by Jetstream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    /// The repo DID, from the envelope
+    #[serde(default)]
+    pub did: String,
+    /// From the envelope, see `time_us` in the Jetstream docs
+    #[serde(default)]
+    pub time_us: i64,
+    pub rev: String,
+    /// create, update or delete
+    pub operation: String,
+    pub collection: String,
+    pub rkey: String,
+    /// The record, for creates and updates
+    pub record: Option<serde_json::Value>,
+    pub cid: Option<Cid>,
+}
+
+impl Commit {
+    /// The path of the record in the repo, as in the firehose ops
+    pub fn path(&self) -> String {
+        format!("{}/{}", self.collection, self.rkey)
+    }
+}
+
+/// Decompress a binary Jetstream message, zstd compressed with the
+/// dictionary published in the Jetstream repository
+pub fn decompress(data: &[u8], dictionary: Option<&[u8]>) -> std::io::Result<String> {
+    use std::io::Read;
+    let mut decoder = zstd::stream::Decoder::with_dictionary(data, dictionary.unwrap_or_default())?;
+    let mut text = String::new();
+    decoder.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Decode a Jetstream message, returning the event along with its cursor
+pub fn decode_jetstream(text: &str) -> Result<(FirehoseEvent, i64), Error> {
+    let envelope: Envelope = serde_json::from_str(text).map_err(Error::Json)?;
+    let event = match (
+        envelope.kind.as_str(),
+        envelope.commit,
+        envelope.identity,
+        envelope.account,
+    ) {
+        ("commit", Some(mut commit), _, _) => {
+            commit.did = envelope.did;
+            commit.time_us = envelope.time_us;
+            FirehoseEvent::JetstreamCommit(commit)
+        }
+        ("identity", _, Some(identity), _) => FirehoseEvent::Identity(identity),
+        ("account", _, _, Some(account)) => FirehoseEvent::Account(account),
+        (kind, _, _, _) => FirehoseEvent::Unknown(kind.to_string()),
+    };
+    Ok((event, envelope.time_us))
+}
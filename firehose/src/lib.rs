@@ -0,0 +1,1011 @@
+//! A client for the `com.atproto.sync.subscribeRepos` event stream of a relay.
+//!
+//! [`Firehose`] connects to the relay and yields the decoded events, keeping
+//! track of the cursor so that it can resume after a disconnect:
+//!
+//! ```no_run
+//! # async fn run() {
+//! let mut firehose = firehose::Firehose::new(firehose::DEFAULT_RELAY, None).unwrap();
+//! let mut backoff = firehose::Backoff::default();
+//! loop {
+//!     match firehose.next_event().await {
+//!         Ok(event) => {
+//!             backoff.reset();
+//!             println!("{:?}", event);
+//!         }
+//!         Err(e) => {
+//!             println!("{}", e);
+//!             tokio::time::sleep(backoff.next_delay()).await;
+//!         }
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! For testing, the frames can also be read from stdin or a Unix domain
+//! socket, as set out in [`framed`], by [`Firehose::from_source`].
+//!
+//! The connections, frames and events are logged with `tracing`, in spans
+//! around connecting and decoding.
+#![allow(non_snake_case, non_camel_case_types)]
+pub mod car;
+pub mod cid;
+pub mod dedup;
+pub mod framed;
+pub mod jetstream;
+pub mod limits;
+pub mod mst;
+pub mod signature;
+
+use car::decode_car;
+use chrono::{DateTime, Utc};
+pub use cid::{record_cid, Cid};
+use futures_util::{Stream, StreamExt};
+pub use limits::{DecodeError, DecodeLimits};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use serde_with::BytesOrString;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncRead;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite, tungstenite::Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{debug, trace, Instrument};
+use url::Url;
+
+/// The subscribeRepos endpoint of the main bsky.network relay
+pub const DEFAULT_RELAY: &str = "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos";
+
+/// The header of each firehose frame: op 1 is a message of type t,
+/// op -1 an error, which ends the stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    pub op: i64,
+    pub t: Option<FrameType>,
+}
+
+/// The type of the message of a frame, `#commit` and so on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum FrameType {
+    Commit,
+    Identity,
+    Account,
+    Handle,
+    Migrate,
+    Tombstone,
+    Info,
+    Error,
+    /// A type this crate does not know about, as it was in the header
+    Unknown(String),
+}
+
+impl FrameType {
+    /// The type as it is in the header, with the `#` prefix
+    pub fn as_str(&self) -> &str {
+        match self {
+            FrameType::Commit => "#commit",
+            FrameType::Identity => "#identity",
+            FrameType::Account => "#account",
+            FrameType::Handle => "#handle",
+            FrameType::Migrate => "#migrate",
+            FrameType::Tombstone => "#tombstone",
+            FrameType::Info => "#info",
+            FrameType::Error => "#error",
+            FrameType::Unknown(t) => t,
+        }
+    }
+}
+
+impl From<&str> for FrameType {
+    /// The type of a header `t`, with or without the `#` prefix
+    fn from(t: &str) -> Self {
+        match t.strip_prefix('#').unwrap_or(t) {
+            "commit" => FrameType::Commit,
+            "identity" => FrameType::Identity,
+            "account" => FrameType::Account,
+            "handle" => FrameType::Handle,
+            "migrate" => FrameType::Migrate,
+            "tombstone" => FrameType::Tombstone,
+            "info" => FrameType::Info,
+            "error" => FrameType::Error,
+            _ => FrameType::Unknown(t.to_string()),
+        }
+    }
+}
+
+impl From<String> for FrameType {
+    fn from(t: String) -> Self {
+        FrameType::from(t.as_str())
+    }
+}
+
+impl From<FrameType> for String {
+    fn from(t: FrameType) -> Self {
+        t.as_str().to_string()
+    }
+}
+
+/// The body of an error frame, e.g. FutureCursor or ConsumerTooSlow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorFrame {
+    pub error: String,
+    pub message: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitRecord {
+    /// The stream sequence number of this message.
+    pub seq: i64,
+    /// DEPRECATED -- unused
+    pub rebase: bool,
+    /// Indicates that this commit contained too many ops, or data size was too large. Consumers will need to make a separate request to get missing data.
+    pub tooBig: Option<bool>,
+    /// The repo this event comes from.
+    pub repo: String,
+    /// Repo commit object CID.
+    pub commit: Cid,
+    /// CAR file containing relevant blocks, as a diff since the previous repo state.
+    #[serde_as(as = "BytesOrString")]
+    pub blocks: Vec<u8>,
+    pub ops: Vec<repoOp>,
+    /// DEPRECATED -- unused. WARNING -- nullable and optional; stick with optional to ensure golang interoperability.
+    #[serde(default)]
+    pub prev: Option<Cid>,
+    /// The rev of the emitted commit. Note that this information is also in the commit object included in blocks, unless this is a tooBig event.
+    pub rev: String,
+    /// The rev of the last emitted commit from this repo (if any).
+    #[serde(default)]
+    pub since: Option<String>,
+    /// New blobs referenced by records in this commit.
+    pub blobs: Vec<Cid>,
+    /// Timestamp of when this message was originally broadcast.
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct repoOp {
+    pub action: String,
+    pub path: String,
+    /// For creates and updates, the new record CID. For deletions, null.
+    pub cid: Option<Cid>,
+}
+
+/// Represents a change to an account's identity. Could be an updated handle,
+/// signing key, or pds hosting endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityEvent {
+    pub seq: i64,
+    pub did: String,
+    pub time: String,
+    /// The current handle for the account, or 'handle.invalid' if validation fails.
+    pub handle: Option<String>,
+}
+
+/// Represents a change to an account's status on a host (eg, PDS or Relay).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountEvent {
+    pub seq: i64,
+    pub did: String,
+    pub time: String,
+    /// Indicates that the account has a repository which can be fetched from the host that emitted this event.
+    pub active: bool,
+    /// If active=false, this optional field indicates a reason for why the account is not active.
+    pub status: Option<String>,
+}
+
+/// DEPRECATED -- Use #identity event instead
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandleEvent {
+    pub seq: i64,
+    pub did: String,
+    pub handle: String,
+    pub time: String,
+}
+
+/// DEPRECATED -- Use #account event instead
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TombstoneEvent {
+    pub seq: i64,
+    pub did: String,
+    pub time: String,
+}
+
+/// DEPRECATED -- Use #account event instead
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateEvent {
+    pub seq: i64,
+    pub did: String,
+    pub migrateTo: Option<String>,
+    pub time: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfoEvent {
+    pub name: String,
+    pub message: Option<String>,
+}
+
+impl repoOp {
+    /// The collection NSID part of the path, before the record key
+    pub fn collection(&self) -> &str {
+        self.path
+            .rsplit_once('/')
+            .map(|(collection, _)| collection)
+            .unwrap_or(&self.path)
+    }
+}
+
+/// An op, with the DAG-CBOR data of its record if there is one
+pub type OpRecord<'a> = (&'a repoOp, Option<Vec<u8>>);
+
+impl CommitRecord {
+    /// Whether any of the ops is in one of the collections
+    pub fn touches_collections(&self, collections: &[String]) -> bool {
+        self.ops
+            .iter()
+            .any(|op| collections.iter().any(|c| c == op.collection()))
+    }
+
+    /// The ops along with the DAG-CBOR data of the created or updated records,
+    /// as found in the blocks. Deletes have no record.
+    pub fn op_records(&self) -> Result<Vec<OpRecord<'_>>, car::CarError> {
+        let mut blocks = decode_car(&self.blocks)?;
+        Ok(self
+            .ops
+            .iter()
+            .map(|op| (op, op.cid.as_ref().and_then(|cid| blocks.remove(cid))))
+            .collect())
+    }
+
+    /// The created or updated records whose block does not hash to the CID
+    /// of their op, along with the CID of the block. The records missing
+    /// from the blocks can't be checked, and are left out.
+    pub fn mismatched_cids(&self) -> Result<Vec<(&repoOp, Cid)>, car::CarError> {
+        let mut mismatched = vec![];
+        for (op, record) in self.op_records()? {
+            let (Some(cid), Some(record)) = (&op.cid, record) else {
+                continue;
+            };
+            let actual = record_cid(&record);
+            if actual != *cid {
+                mismatched.push((op, actual));
+            }
+        }
+        Ok(mismatched)
+    }
+
+    /// Verify that the commit is signed with the key, which should be the
+    /// signing key from the DID document of the repo
+    pub fn verify_signature(
+        &self,
+        signing_key: &signature::PublicKey,
+    ) -> Result<(), signature::SignatureError> {
+        let bad_commit = signature::SignatureError::BadCommit;
+        let blocks = decode_car(&self.blocks).map_err(|e| bad_commit(e.to_string()))?;
+        let block = blocks
+            .get(&self.commit)
+            .ok_or_else(|| bad_commit(format!("no block for {}", self.commit)))?;
+        signature::verify_commit(block, signing_key)
+    }
+}
+
+/// Decode the DAG-CBOR data of a record as JSON
+pub fn record_json(data: &[u8]) -> Option<serde_json::Value> {
+    serde_cbor::from_slice(data).ok().map(cbor_to_json)
+}
+
+/// Convert DAG-CBOR data to JSON, with byte strings in the `$bytes` form
+pub fn cbor_to_json(value: serde_cbor::Value) -> serde_json::Value {
+    use base64::Engine;
+    use serde_cbor::Value as Cbor;
+    use serde_json::Value as Json;
+    match value {
+        Cbor::Null => Json::Null,
+        Cbor::Bool(b) => Json::Bool(b),
+        Cbor::Integer(i) => match i64::try_from(i) {
+            Ok(i) => Json::from(i),
+            Err(_) => Json::String(i.to_string()),
+        },
+        Cbor::Float(f) => Json::from(f),
+        Cbor::Bytes(b) => {
+            let encoded = base64::engine::general_purpose::STANDARD_NO_PAD.encode(b);
+            serde_json::json!({ "$bytes": encoded })
+        }
+        Cbor::Text(s) => Json::String(s),
+        Cbor::Array(a) => Json::Array(a.into_iter().map(cbor_to_json).collect()),
+        Cbor::Map(m) => Json::Object(
+            m.into_iter()
+                .map(|(k, v)| {
+                    let key = match k {
+                        Cbor::Text(s) => s,
+                        other => format!("{:?}", other),
+                    };
+                    (key, cbor_to_json(v))
+                })
+                .collect(),
+        ),
+        Cbor::Tag(cid::CID_TAG, v) => match *v {
+            Cbor::Bytes(b) => match b.split_first().and_then(|(_, cid)| Cid::from_bytes(cid)) {
+                Some(cid) => serde_json::json!({ "$link": cid.to_string() }),
+                None => cbor_to_json(Cbor::Bytes(b)),
+            },
+            v => cbor_to_json(v),
+        },
+        Cbor::Tag(_, v) => cbor_to_json(*v),
+        _ => Json::Null,
+    }
+}
+
+/// A commit with too many ops or too much data for its blocks to be
+/// included. The records need to be fetched separately, e.g. with
+/// `com.atproto.sync.getRecord`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TooBigCommit {
+    pub seq: i64,
+    pub repo: String,
+    pub rev: String,
+    pub time: DateTime<Utc>,
+    /// The paths of the records the ops are about, `<collection>/<rkey>`
+    pub paths: Vec<String>,
+}
+
+impl TooBigCommit {
+    /// Whether any of the records is in one of the collections
+    pub fn touches_collections(&self, collections: &[String]) -> bool {
+        self.paths.iter().any(|path| {
+            let collection = path.split('/').next().unwrap_or_default();
+            collections.iter().any(|c| c == collection)
+        })
+    }
+}
+
+impl From<CommitRecord> for TooBigCommit {
+    fn from(cr: CommitRecord) -> Self {
+        TooBigCommit {
+            seq: cr.seq,
+            repo: cr.repo,
+            rev: cr.rev,
+            time: cr.time,
+            paths: cr.ops.into_iter().map(|op| op.path).collect(),
+        }
+    }
+}
+
+/// A decoded firehose message
+#[derive(Debug, Clone)]
+pub enum FirehoseEvent {
+    Commit(CommitRecord),
+    /// A commit marked `tooBig`, whose blocks are incomplete
+    CommitTooBig(TooBigCommit),
+    Identity(IdentityEvent),
+    Account(AccountEvent),
+    Handle(HandleEvent),
+    Tombstone(TombstoneEvent),
+    Migrate(MigrateEvent),
+    Info(InfoEvent),
+    /// A commit op from Jetstream, which has no blocks but the record as JSON
+    JetstreamCommit(jetstream::Commit),
+    /// A message of a type (or a frame op) this crate does not know about
+    Unknown(String),
+}
+
+impl FirehoseEvent {
+    /// The stream sequence number, for the events that have one
+    pub fn seq(&self) -> Option<i64> {
+        match self {
+            FirehoseEvent::Commit(ev) => Some(ev.seq),
+            FirehoseEvent::CommitTooBig(ev) => Some(ev.seq),
+            FirehoseEvent::Identity(ev) => Some(ev.seq),
+            FirehoseEvent::Account(ev) => Some(ev.seq),
+            FirehoseEvent::Handle(ev) => Some(ev.seq),
+            FirehoseEvent::Tombstone(ev) => Some(ev.seq),
+            FirehoseEvent::Migrate(ev) => Some(ev.seq),
+            FirehoseEvent::Info(_)
+            | FirehoseEvent::JetstreamCommit(_)
+            | FirehoseEvent::Unknown(_) => None,
+        }
+    }
+
+    /// When the event was broadcast, the same for both transports: the
+    /// `time` of the firehose events, or the `time_us` of a Jetstream commit,
+    /// when Jetstream got it. None for the events without a time, or with
+    /// one that is not RFC 3339.
+    pub fn event_time(&self) -> Option<DateTime<Utc>> {
+        let time = match self {
+            FirehoseEvent::Commit(ev) => return Some(ev.time),
+            FirehoseEvent::CommitTooBig(ev) => return Some(ev.time),
+            FirehoseEvent::JetstreamCommit(commit) if commit.time_us != 0 => {
+                return DateTime::from_timestamp_micros(commit.time_us);
+            }
+            FirehoseEvent::Identity(ev) => &ev.time,
+            FirehoseEvent::Account(ev) => &ev.time,
+            FirehoseEvent::Handle(ev) => &ev.time,
+            FirehoseEvent::Tombstone(ev) => &ev.time,
+            FirehoseEvent::Migrate(ev) => &ev.time,
+            FirehoseEvent::JetstreamCommit(_)
+            | FirehoseEvent::Info(_)
+            | FirehoseEvent::Unknown(_) => return None,
+        };
+        DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    }
+
+    /// How long before `now` the event was broadcast, by its event_time().
+    /// It is negative for an event from the future, as the clocks of the
+    /// relay and of this machine never quite agree.
+    pub fn age(&self, now: DateTime<Utc>) -> Option<chrono::TimeDelta> {
+        Some(now - self.event_time()?)
+    }
+
+    /// How far behind `now` the stream is at the event: its age, an event
+    /// from the future being on time.
+    pub fn lag(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.age(now).map(|age| age.to_std().unwrap_or_default())
+    }
+
+    /// The message type, without the leading `#`
+    pub fn kind(&self) -> &str {
+        match self {
+            FirehoseEvent::Commit(_)
+            | FirehoseEvent::CommitTooBig(_)
+            | FirehoseEvent::JetstreamCommit(_) => "commit",
+            FirehoseEvent::Identity(_) => "identity",
+            FirehoseEvent::Account(_) => "account",
+            FirehoseEvent::Handle(_) => "handle",
+            FirehoseEvent::Tombstone(_) => "tombstone",
+            FirehoseEvent::Migrate(_) => "migrate",
+            FirehoseEvent::Info(_) => "info",
+            FirehoseEvent::Unknown(t) => t.trim_start_matches('#'),
+        }
+    }
+
+    /// The event as a JSON object with its `type`. Commits have their ops
+    /// listed with the decoded records instead of the raw blocks.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut value = match self {
+            FirehoseEvent::Commit(cr) => {
+                let ops = match cr.op_records() {
+                    Ok(ops) => ops
+                        .into_iter()
+                        .map(|(op, record)| {
+                            serde_json::json!({
+                                "action": op.action,
+                                "path": op.path,
+                                "cid": op.cid,
+                                "record": record.and_then(|data| record_json(&data)),
+                            })
+                        })
+                        .collect(),
+                    Err(_) => vec![],
+                };
+                serde_json::json!({
+                    "seq": cr.seq,
+                    "repo": cr.repo,
+                    "commit": cr.commit,
+                    "rev": cr.rev,
+                    "since": cr.since,
+                    "time": cr.time,
+                    "tooBig": cr.tooBig,
+                    "blobs": cr.blobs,
+                    "ops": ops,
+                })
+            }
+            FirehoseEvent::CommitTooBig(ev) => serde_json::json!({
+                "seq": ev.seq,
+                "repo": ev.repo,
+                "rev": ev.rev,
+                "time": ev.time,
+                "tooBig": true,
+                "ops": ev.paths.iter().map(|path| serde_json::json!({ "path": path })).collect::<Vec<_>>(),
+            }),
+            FirehoseEvent::Identity(ev) => serde_json::to_value(ev).unwrap_or_default(),
+            FirehoseEvent::Account(ev) => serde_json::to_value(ev).unwrap_or_default(),
+            FirehoseEvent::Handle(ev) => serde_json::to_value(ev).unwrap_or_default(),
+            FirehoseEvent::Tombstone(ev) => serde_json::to_value(ev).unwrap_or_default(),
+            FirehoseEvent::Migrate(ev) => serde_json::to_value(ev).unwrap_or_default(),
+            FirehoseEvent::Info(ev) => serde_json::to_value(ev).unwrap_or_default(),
+            // the same shape as for the firehose, with a single op
+            FirehoseEvent::JetstreamCommit(commit) => serde_json::json!({
+                "repo": commit.did,
+                "rev": commit.rev,
+                "time": self.event_time(),
+                "time_us": commit.time_us,
+                "ops": [{
+                    "action": commit.operation,
+                    "path": commit.path(),
+                    "cid": commit.cid,
+                    "record": commit.record,
+                }],
+            }),
+            FirehoseEvent::Unknown(_) => serde_json::json!({}),
+        };
+        if let Some(obj) = value.as_object_mut() {
+            obj.insert("type".to_string(), self.kind().into());
+        }
+        value
+    }
+
+    /// The DID of the repo or account the event is about, for the events that have one
+    pub fn did(&self) -> Option<&str> {
+        match self {
+            FirehoseEvent::Commit(ev) => Some(&ev.repo),
+            FirehoseEvent::CommitTooBig(ev) => Some(&ev.repo),
+            FirehoseEvent::Identity(ev) => Some(&ev.did),
+            FirehoseEvent::Account(ev) => Some(&ev.did),
+            FirehoseEvent::Handle(ev) => Some(&ev.did),
+            FirehoseEvent::Tombstone(ev) => Some(&ev.did),
+            FirehoseEvent::Migrate(ev) => Some(&ev.did),
+            FirehoseEvent::JetstreamCommit(ev) => Some(&ev.did),
+            FirehoseEvent::Info(_) | FirehoseEvent::Unknown(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The relay URL is not valid
+    Url(url::ParseError),
+    Connect(Box<tungstenite::Error>),
+    Read(Box<tungstenite::Error>),
+    /// A stream of length-prefixed frames could not be opened or read
+    Io(std::io::Error),
+    /// No message arrived within the read timeout
+    Timeout(Duration),
+    /// The relay closed the connection
+    Closed,
+    /// A frame could not be decoded
+    Decode(DecodeError),
+    /// A Jetstream message could not be decoded
+    Json(serde_json::Error),
+    /// A compressed Jetstream message could not be decompressed
+    Decompress(std::io::Error),
+    /// The relay sent an error frame, and will disconnect
+    Frame(ErrorFrame),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Url(e) => write!(f, "invalid relay URL: {}", e),
+            Error::Connect(e) => write!(f, "can't connect: {}", e),
+            Error::Read(e) => write!(f, "error reading message: {}", e),
+            Error::Io(e) => write!(f, "error reading frames: {}", e),
+            Error::Timeout(d) => write!(f, "no message for {:?}", d),
+            Error::Closed => write!(f, "connection closed"),
+            Error::Decode(e) => write!(f, "can't decode frame: {}", e),
+            Error::Json(e) => write!(f, "can't decode Jetstream message: {}", e),
+            Error::Decompress(e) => write!(f, "can't decompress Jetstream message: {}", e),
+            Error::Frame(err) => write!(
+                f,
+                "error frame: {} {}",
+                err.error,
+                err.message.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Whether only the message is lost, the connection being still usable
+    pub fn is_decode(&self) -> bool {
+        matches!(
+            self,
+            Error::Decode(_) | Error::Json(_) | Error::Decompress(_)
+        )
+    }
+}
+
+/// Decode one binary frame: a header, followed by the message body.
+/// An error frame is returned as `Error::Frame`.
+pub fn decode_frame(data: &[u8]) -> Result<FirehoseEvent, Error> {
+    decode_frame_with_limits(data, &DecodeLimits::default())
+}
+
+/// Decode one binary frame, after checking that it is within the limits
+pub fn decode_frame_with_limits(
+    data: &[u8],
+    limits: &DecodeLimits,
+) -> Result<FirehoseEvent, Error> {
+    limits::check_cbor(data, limits).map_err(Error::Decode)?;
+    let mut deserializer = serde_cbor::Deserializer::from_slice(data);
+    let hdr: Frame = Deserialize::deserialize(&mut deserializer)?;
+    if hdr.op < 0 {
+        let err: ErrorFrame = Deserialize::deserialize(&mut deserializer)?;
+        return Err(Error::Frame(err));
+    }
+    if hdr.op != 1 {
+        return Ok(FirehoseEvent::Unknown(format!("op {}", hdr.op)));
+    }
+    let Some(t) = hdr.t else {
+        return Ok(FirehoseEvent::Unknown(String::new()));
+    };
+    Ok(match t {
+        FrameType::Commit => {
+            let cr: CommitRecord = Deserialize::deserialize(&mut deserializer)?;
+            // the blocks are incomplete, and can't be used to get the records
+            if cr.tooBig == Some(true) {
+                FirehoseEvent::CommitTooBig(cr.into())
+            } else {
+                FirehoseEvent::Commit(cr)
+            }
+        }
+        FrameType::Identity => {
+            FirehoseEvent::Identity(Deserialize::deserialize(&mut deserializer)?)
+        }
+        FrameType::Account => FirehoseEvent::Account(Deserialize::deserialize(&mut deserializer)?),
+        FrameType::Handle => FirehoseEvent::Handle(Deserialize::deserialize(&mut deserializer)?),
+        FrameType::Tombstone => {
+            FirehoseEvent::Tombstone(Deserialize::deserialize(&mut deserializer)?)
+        }
+        FrameType::Migrate => FirehoseEvent::Migrate(Deserialize::deserialize(&mut deserializer)?),
+        FrameType::Info => FirehoseEvent::Info(Deserialize::deserialize(&mut deserializer)?),
+        // errors have an op of -1, there is no error message type
+        other @ (FrameType::Error | FrameType::Unknown(_)) => {
+            FirehoseEvent::Unknown(other.as_str().to_string())
+        }
+    })
+}
+
+impl From<serde_cbor::Error> for Error {
+    fn from(e: serde_cbor::Error) -> Self {
+        Error::Decode(DecodeError::Cbor(e))
+    }
+}
+
+/// Exponential backoff between reconnects, with jitter.
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Backoff {
+            base,
+            max,
+            current: base,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.base;
+    }
+
+    /// The delay before the next attempt, somewhere in the upper half of
+    /// the current step so that many clients don't reconnect in lockstep
+    pub fn next_delay(&mut self) -> Duration {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let jitter = 0.5 + (nanos % 1000) as f64 / 2000.0;
+        let delay = self.current.mul_f64(jitter);
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+}
+
+impl Default for Backoff {
+    /// From one second up to a minute
+    fn default() -> Self {
+        Backoff::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+/// A jump in the sequence numbers, from `prev` to `seq` instead of `prev + 1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeqGap {
+    pub prev: i64,
+    pub seq: i64,
+}
+
+impl SeqGap {
+    /// How many events were skipped, negative when the sequence went backwards
+    pub fn missed(&self) -> i64 {
+        self.seq - self.prev - 1
+    }
+}
+
+/// Where the messages of a [`Firehose`] come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// The websocket of a relay or Jetstream, at this URL
+    WebSocket(Url),
+    /// The messages of a relay as [`framed`] on stdin, which can't be
+    /// reopened once it ends
+    Stdin,
+    /// The messages of a relay as [`framed`] on the Unix domain socket at
+    /// this path, such as that of a local proxy
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for Source {
+    type Err = Error;
+
+    /// `stdin`, `unix:<path>`, or the URL of a websocket
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "stdin" {
+            Ok(Source::Stdin)
+        } else if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Source::Unix(PathBuf::from(path)))
+        } else {
+            Url::parse(s).map(Source::WebSocket).map_err(Error::Url)
+        }
+    }
+}
+
+/// An open source of messages
+enum Connection {
+    WebSocket(Box<WebSocketStream<MaybeTlsStream<TcpStream>>>),
+    Framed(Box<dyn AsyncRead + Send + Sync + Unpin>),
+}
+
+/// A subscription to the event stream of a relay.
+///
+/// Connects on the first call to `next_event`, and again on the one after
+/// an error, resuming after the last seq seen.
+pub struct Firehose {
+    source: Source,
+    cursor: Option<i64>,
+    read_timeout: Duration,
+    connection: Option<Connection>,
+    /// Whether this is a Jetstream, sending JSON with `time_us` cursors
+    jetstream: bool,
+    /// For the zstd compressed messages of a Jetstream
+    zstd_dictionary: Option<Vec<u8>>,
+    /// seq of the previous event, to detect gaps
+    prev_seq: Option<i64>,
+    last_gap: Option<SeqGap>,
+    gaps: u64,
+    limits: DecodeLimits,
+    /// How many messages could not be decoded
+    decode_errors: u64,
+}
+
+impl Firehose {
+    /// Subscribe to the relay at the given subscribeRepos URL, starting after
+    /// the cursor, or with the live stream if there is none.
+    pub fn new(relay: &str, cursor: Option<i64>) -> Result<Self, Error> {
+        let relay = Url::parse(relay).map_err(Error::Url)?;
+        Ok(Firehose::from_source(Source::WebSocket(relay), cursor))
+    }
+
+    /// Read the messages of a relay from the source. The cursor is only
+    /// sent to websockets, a stream of frames starting where it does.
+    pub fn from_source(source: Source, cursor: Option<i64>) -> Self {
+        Firehose {
+            source,
+            cursor,
+            read_timeout: Duration::from_secs(60),
+            connection: None,
+            jetstream: false,
+            zstd_dictionary: None,
+            prev_seq: None,
+            last_gap: None,
+            gaps: 0,
+            limits: DecodeLimits::default(),
+            decode_errors: 0,
+        }
+    }
+
+    /// Subscribe to a Jetstream at the given URL, starting at the cursor,
+    /// a time in microseconds since the epoch
+    pub fn jetstream(url: &str, cursor: Option<i64>) -> Result<Self, Error> {
+        Ok(Firehose {
+            jetstream: true,
+            ..Firehose::new(url, cursor)?
+        })
+    }
+
+    /// The dictionary Jetstream compresses its messages with, when asked
+    /// to with `compress=true`
+    pub fn with_zstd_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.zstd_dictionary = Some(dictionary);
+        self
+    }
+
+    /// Skip the frames that are not within these limits, rather than the
+    /// default ones
+    pub fn with_decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Consider the connection dead when no message arrives for this long,
+    /// except on stdin, which is waited on for as long as it takes
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// The last seq seen (for a Jetstream, the last time_us), to resume from
+    pub fn cursor(&self) -> Option<i64> {
+        self.cursor
+    }
+
+    /// The gap in the sequence just before the last event returned, if any.
+    /// Gaps are not errors, but a complete mirror would need a backfill.
+    pub fn last_gap(&self) -> Option<SeqGap> {
+        self.last_gap
+    }
+
+    /// How many gaps in the sequence were seen so far
+    pub fn gap_count(&self) -> u64 {
+        self.gaps
+    }
+
+    /// How many messages could not be decoded so far, and were skipped
+    pub fn decode_error_count(&self) -> u64 {
+        self.decode_errors
+    }
+
+    /// Whether the source can be connected to again after an error
+    pub fn can_reconnect(&self) -> bool {
+        self.source != Source::Stdin
+    }
+
+    /// The subscription URL, resuming after the cursor if there is one,
+    /// None if the source is not a websocket.
+    /// Relays only keep a limited backfill window (e.g. 72 hours on bsky.network),
+    /// an older cursor starts from the oldest event still available.
+    pub fn subscribe_url(&self) -> Option<Url> {
+        let Source::WebSocket(relay) = &self.source else {
+            return None;
+        };
+        let mut url = relay.clone();
+        if let Some(cursor) = self.cursor {
+            url.query_pairs_mut()
+                .append_pair("cursor", &cursor.to_string());
+        }
+        Some(url)
+    }
+
+    async fn connect(&self) -> Result<Connection, Error> {
+        let connection = match &self.source {
+            Source::WebSocket(_) => {
+                let url = self.subscribe_url().unwrap();
+                let span = tracing::info_span!("connect", url = %url);
+                let (socket, _response) = connect_async(url.as_str())
+                    .instrument(span.clone())
+                    .await
+                    .map_err(|e| Error::Connect(Box::new(e)))?;
+                span.in_scope(|| debug!("connected"));
+                Connection::WebSocket(Box::new(socket))
+            }
+            Source::Stdin => Connection::Framed(Box::new(tokio::io::stdin())),
+            Source::Unix(path) => {
+                let span = tracing::info_span!("connect", path = %path.display());
+                let socket = tokio::net::UnixStream::connect(path)
+                    .instrument(span.clone())
+                    .await
+                    .map_err(Error::Io)?;
+                span.in_scope(|| debug!("connected"));
+                Connection::Framed(Box::new(socket))
+            }
+        };
+        Ok(connection)
+    }
+
+    /// Wait for the next event, connecting first if needed. After an error
+    /// the connection is dropped, the next call reconnects, except for a
+    /// message that could not be decoded, which is just skipped.
+    pub async fn next_event(&mut self) -> Result<FirehoseEvent, Error> {
+        let result = self.read_event().await;
+        if let Err(e) = &result {
+            if e.is_decode() {
+                self.decode_errors += 1;
+                return result;
+            }
+            self.connection = None;
+            // the cursor is ahead of the relay, start from the live stream
+            if let Error::Frame(err) = e {
+                if err.error == "FutureCursor" {
+                    self.cursor = None;
+                }
+            }
+        }
+        result
+    }
+
+    async fn read_event(&mut self) -> Result<FirehoseEvent, Error> {
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => {
+                let connection = self.connect().await?;
+                self.connection.insert(connection)
+            }
+        };
+        // a relay gone quiet is as good as disconnected
+        let read_timeout = match self.source {
+            Source::Stdin => Duration::MAX,
+            _ => self.read_timeout,
+        };
+        loop {
+            let msg = match connection {
+                Connection::WebSocket(socket) => {
+                    match tokio::time::timeout(read_timeout, socket.next()).await {
+                        Ok(Some(Ok(msg))) => msg,
+                        Ok(Some(Err(e))) => return Err(Error::Read(Box::new(e))),
+                        Ok(None) => return Err(Error::Closed),
+                        Err(_) => return Err(Error::Timeout(read_timeout)),
+                    }
+                }
+                Connection::Framed(reader) => {
+                    let frame = framed::read_frame(reader, self.limits.max_frame_len);
+                    match tokio::time::timeout(read_timeout, frame).await {
+                        Ok(Ok(Some(frame))) => Message::Binary(frame),
+                        Ok(Ok(None)) => return Err(Error::Closed),
+                        Ok(Err(e)) => return Err(Error::Io(e)),
+                        Err(_) => return Err(Error::Timeout(read_timeout)),
+                    }
+                }
+            };
+            match msg {
+                Message::Binary(b) if self.jetstream => {
+                    trace!(len = b.len(), "compressed message");
+                    let text = jetstream::decompress(&b, self.zstd_dictionary.as_deref())
+                        .map_err(Error::Decompress)?;
+                    let (event, time_us) = decode_span(text.len()).in_scope(|| {
+                        jetstream::decode_jetstream(&text).inspect(|(event, _)| log_event(event))
+                    })?;
+                    self.cursor = self.cursor.max(Some(time_us));
+                    return Ok(event);
+                }
+                Message::Binary(b) => {
+                    trace!(len = b.len(), "frame");
+                    let event = decode_span(b.len()).in_scope(|| {
+                        decode_frame_with_limits(&b, &self.limits).inspect(log_event)
+                    })?;
+                    self.cursor = self.cursor.max(event.seq());
+                    self.last_gap = None;
+                    if let Some(seq) = event.seq() {
+                        if let Some(prev) = self.prev_seq.filter(|prev| seq != prev + 1) {
+                            self.last_gap = Some(SeqGap { prev, seq });
+                            self.gaps += 1;
+                        }
+                        self.prev_seq = Some(seq);
+                    }
+                    return Ok(event);
+                }
+                Message::Text(text) if self.jetstream => {
+                    trace!(len = text.len(), "message");
+                    let (event, time_us) = decode_span(text.len()).in_scope(|| {
+                        jetstream::decode_jetstream(&text).inspect(|(event, _)| log_event(event))
+                    })?;
+                    self.cursor = self.cursor.max(Some(time_us));
+                    return Ok(event);
+                }
+                Message::Close(_) => return Err(Error::Closed),
+                // pings are answered by tungstenite
+                _ => {}
+            }
+        }
+    }
+
+    /// The events as a stream, which ends at the first error
+    pub fn into_stream(self) -> impl Stream<Item = Result<FirehoseEvent, Error>> {
+        futures_util::stream::unfold(Some(self), |firehose| async move {
+            let mut firehose = firehose?;
+            match firehose.next_event().await {
+                Ok(event) => Some((Ok(event), Some(firehose))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
+
+/// The span decoding a message of `len` bytes is in
+fn decode_span(len: usize) -> tracing::Span {
+    tracing::debug_span!("decode", len)
+}
+
+fn log_event(event: &FirehoseEvent) {
+    debug!(kind = event.kind(), seq = ?event.seq(), did = ?event.did(), "event");
+}
@@ -0,0 +1,137 @@
+//! Limits on the frames decoded, as relays are not trusted to send sane ones.
+//!
+//! serde_cbor deserializes whatever lengths the data declares, so before
+//! handing a frame over to it, [`check_cbor`] walks the headers of its CBOR
+//! items: each length must fit in what is left of the frame and within the
+//! limits, and the nesting must not be too deep.
+
+/// Bounds on the size and shape of a decoded frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// The largest frame, in bytes
+    pub max_frame_len: usize,
+    /// The most items an array, or entries a map, can have
+    pub max_collection_len: u64,
+    /// How deep arrays, maps and tags can be nested
+    pub max_depth: usize,
+}
+
+impl Default for DecodeLimits {
+    /// Well above what relays send: frames of commits with too many ops or
+    /// blocks are cut short with `tooBig`, at about 1 MB
+    fn default() -> Self {
+        DecodeLimits {
+            max_frame_len: 8 << 20,
+            max_collection_len: 1 << 16,
+            max_depth: 64,
+        }
+    }
+}
+
+/// Why a frame could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The CBOR is well-formed, but not what was expected
+    Cbor(serde_cbor::Error),
+    /// The frame is longer than the limit
+    FrameTooLong(usize),
+    /// An array or map declares more items than the limit
+    TooManyItems(u64),
+    /// Items are nested deeper than the limit
+    TooDeep,
+    /// A length goes past the end of the frame
+    Truncated,
+    /// An initial byte that DAG-CBOR does not allow, such as the start of
+    /// an indefinite length item
+    BadItem(u8),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Cbor(e) => write!(f, "{}", e),
+            DecodeError::FrameTooLong(len) => write!(f, "frame of {} bytes is too long", len),
+            DecodeError::TooManyItems(len) => write!(f, "collection of {} items is too long", len),
+            DecodeError::TooDeep => write!(f, "items are nested too deep"),
+            DecodeError::Truncated => write!(f, "data is truncated"),
+            DecodeError::BadItem(byte) => write!(f, "invalid CBOR item 0x{:02x}", byte),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Check that the data is a sequence of CBOR items within the limits, as a
+/// frame is a header followed by a body.
+pub fn check_cbor(data: &[u8], limits: &DecodeLimits) -> Result<(), DecodeError> {
+    if data.len() > limits.max_frame_len {
+        return Err(DecodeError::FrameTooLong(data.len()));
+    }
+    let mut pos = 0;
+    while pos < data.len() {
+        check_item(data, &mut pos, 0, limits)?;
+    }
+    Ok(())
+}
+
+/// Check the item at `pos`, moving past it. The recursion is bounded by
+/// the depth limit.
+fn check_item(
+    data: &[u8],
+    pos: &mut usize,
+    depth: usize,
+    limits: &DecodeLimits,
+) -> Result<(), DecodeError> {
+    let initial = *data.get(*pos).ok_or(DecodeError::Truncated)?;
+    *pos += 1;
+    let major = initial >> 5;
+    let arg_len = match initial & 0x1f {
+        0..=23 => 0,
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(DecodeError::BadItem(initial)),
+    };
+    let arg_end = pos.checked_add(arg_len).ok_or(DecodeError::Truncated)?;
+    let arg_bytes = data.get(*pos..arg_end).ok_or(DecodeError::Truncated)?;
+    let arg = if arg_len == 0 {
+        (initial & 0x1f) as u64
+    } else {
+        arg_bytes
+            .iter()
+            .fold(0u64, |arg, byte| (arg << 8) | *byte as u64)
+    };
+    *pos = arg_end;
+    let remaining = (data.len() - *pos) as u64;
+    let items = match major {
+        // integers, and simple values or floats
+        0 | 1 | 7 => return Ok(()),
+        // byte and text strings
+        2 | 3 => {
+            if arg > remaining {
+                return Err(DecodeError::Truncated);
+            }
+            *pos += arg as usize;
+            return Ok(());
+        }
+        4 => arg,
+        5 => arg.checked_mul(2).ok_or(DecodeError::TooManyItems(arg))?,
+        // a tag, such as 42 for CIDs, applies to the one item after it
+        _ => 1,
+    };
+    if major != 6 && arg > limits.max_collection_len {
+        return Err(DecodeError::TooManyItems(arg));
+    }
+    // each item takes at least a byte
+    if items > remaining {
+        return Err(DecodeError::Truncated);
+    }
+    if depth >= limits.max_depth {
+        return Err(DecodeError::TooDeep);
+    }
+    for _ in 0..items {
+        check_item(data, pos, depth + 1, limits)?;
+    }
+    Ok(())
+}
@@ -1,75 +1,675 @@
+use clap::Parser as ClapParser;
+use firehose::cid::DAG_CBOR;
+use firehose::dedup::{LruSeenOps, SeenOps};
+use firehose::{Backoff, Cid, Error, Firehose, FirehoseEvent, SeqGap};
 use serde::{Deserialize, Serialize};
-use serde_with::serde_as;
-use tungstenite::{connect, Message};
-use url::Url;
-use serde_with::BytesOrString;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
+/// Print the ops of a commit, with the created and updated records as JSON
+fn print_commit(cr: &firehose::CommitRecord) {
+    println!("commit: {} seq {}", &cr.repo, cr.seq);
+    match cr.op_records() {
+        Ok(ops) => {
+            for (op, record) in ops {
+                let json = record
+                    .and_then(|data| firehose::record_json(&data))
+                    .map(|value| value.to_string())
+                    .unwrap_or_default();
+                println!("{} {} {}", op.action, op.path, json);
+            }
+        }
+        Err(e) => println!("blocks: {}", e),
+    }
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Frame {
-    op: u64,
-    t: String,
+/// How far ahead of the clock events can be before it is worth a warning,
+/// the clocks of the relay and of this machine being too far apart
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5);
+
+/// Print the roots and blocks of a CAR file, as dumped by --dump-car, with
+/// the DAG-CBOR records as JSON. This is only a dump of the blocks: without
+/// the ops of the commit, which are not in the CAR, there is nothing to say
+/// which record is at which path.
+fn print_car(fname: &str, format: Format) -> Result<(), String> {
+    let data = std::fs::read(fname).map_err(|e| format!("could not read {}: {}", fname, e))?;
+    let car = firehose::car::read_car(&data).map_err(|e| format!("{}: {}", fname, e))?;
+    for root in &car.roots {
+        match format {
+            Format::Text => println!("root: {}", root),
+            Format::Ndjson => println!("{}", serde_json::json!({ "root": root })),
+        }
+    }
+    for (cid, block) in &car.blocks {
+        let record = (cid.codec() == DAG_CBOR)
+            .then(|| firehose::record_json(block))
+            .flatten();
+        match (format, record) {
+            (Format::Text, Some(record)) => println!("{} {}", cid, record),
+            (Format::Text, None) => println!("{} <{} bytes>", cid, block.len()),
+            (Format::Ndjson, record) => println!(
+                "{}",
+                serde_json::json!({ "cid": cid, "size": block.len(), "record": record })
+            ),
+        }
+    }
+    Ok(())
 }
-#[serde_as]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CommitRecord {
-    /// The stream sequence number of this message.
-    pub seq: i64,
-    /// DEPRECATED -- unused
-    pub rebase: bool,
-    /// Indicates that this commit contained too many ops, or data size was too large. Consumers will need to make a separate request to get missing data.
-    pub tooBig: Option<bool>,
-    /// The repo this event comes from.
-    pub repo: String,
-    /// Repo commit object CID.
-    #[serde_as(as = "BytesOrString")]
-    pub commit: Vec<u8>,
+
+/// Counts of the events seen since the last report
+#[derive(Debug)]
+struct Stats {
+    since: Instant,
+    events: BTreeMap<String, u64>,
+    commits: u64,
+    ops: u64,
+    /// Messages skipped as they could not be decoded
+    decode_errors: u64,
+    /// Messages lost as the processing was behind
+    dropped: u64,
+    /// The lags of the events that have a time
+    lags: u64,
+    lag_total: Duration,
+    lag_max: Duration,
 }
-/*
-    /// DEPRECATED -- unused. WARNING -- nullable and optional; stick with optional to ensure golang interoperability.
-    pub prev: Option<String>,
-    /// The rev of the emitted commit. Note that this information is also in the commit object included in blocks, unless this is a tooBig event.
-    pub rev: String,
-    /// The rev of the last emitted commit from this repo (if any).
-    pub since: Option<String>,
-    /// CAR file containing relevant blocks, as a diff since the previous repo state.
-    pub blocks: Vec<u8>,
-    pub ops: Vec<repoOp>,
-    pub blobs: Vec<String>,
-    /// Timestamp of when this message was originally broadcast.
-    pub time: String,
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            since: Instant::now(),
+            events: BTreeMap::new(),
+            commits: 0,
+            ops: 0,
+            decode_errors: 0,
+            dropped: 0,
+            lags: 0,
+            lag_total: Duration::ZERO,
+            lag_max: Duration::ZERO,
+        }
+    }
+
+    fn update(&mut self, event: &FirehoseEvent, lag: Option<Duration>) {
+        *self.events.entry(event.kind().to_string()).or_default() += 1;
+        if let Some(lag) = lag {
+            self.lags += 1;
+            self.lag_total += lag;
+            self.lag_max = self.lag_max.max(lag);
+        }
+        match event {
+            FirehoseEvent::Commit(cr) => {
+                self.commits += 1;
+                self.ops += cr.ops.len() as u64;
+            }
+            FirehoseEvent::CommitTooBig(commit) => {
+                self.commits += 1;
+                self.ops += commit.paths.len() as u64;
+            }
+            _ => {}
+        }
+    }
+
+    /// Print the counts and rates, and start counting afresh
+    fn flush(&mut self, cursor: Option<i64>, dropped: u64) {
+        let secs = self.since.elapsed().as_secs_f64().max(0.001);
+        let counts: Vec<String> = self
+            .events
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect();
+        let lag_avg = self
+            .lag_total
+            .checked_div(self.lags as u32)
+            .unwrap_or_default();
+        info!(
+            "stats: {:.1} commits/s, {:.1} ops/s, lag avg {} ms max {} ms, {} decode errors, {} dropped ({} in all), seq {:?}: {}",
+            self.commits as f64 / secs,
+            self.ops as f64 / secs,
+            lag_avg.as_millis(),
+            self.lag_max.as_millis(),
+            self.decode_errors,
+            self.dropped,
+            dropped,
+            cursor,
+            counts.join(", ")
+        );
+        *self = Stats::new();
+    }
 }
 
-*/
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct repoOp {
-    pub action: String,
-    pub path: String,
-    /// For creates and updates, the new record CID. For deletions, null.
-    pub cid: Option<String>,
+/// A message read from the stream, with the state of the subscription after it
+struct Read {
+    result: Result<FirehoseEvent, Error>,
+    cursor: Option<i64>,
+    gap: Option<SeqGap>,
+    /// How many messages were dropped between the one before and this one
+    dropped: u64,
 }
 
-fn main() {
-    let uri = "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos";
-    let (mut socket, response) = connect(Url::parse(uri).unwrap()).expect("Can't connect");
+/// Read the stream ahead of the processing of the events, reconnecting as
+/// needed. When the processing falls behind and the channel is full, the
+/// messages are counted in `dropped` and lost, as blocking here would have
+/// the relay disconnect a slow consumer. The next message sent says how
+/// many were dropped before it.
+async fn read_ahead(
+    mut firehose: Firehose,
+    mut backoff: Backoff,
+    tx: mpsc::Sender<Read>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut behind = 0;
     loop {
-        let msg = socket.read_message().expect("Error reading message");
-        match &msg {
-            Message::Binary(b) => {
-                println!("Binary: {}", b.len());
-                let mut data = b.clone();
-                let mut deserializer = serde_cbor::Deserializer::from_mut_slice(&mut data);
-                let hdr: Frame = serde::Deserialize::deserialize(&mut deserializer).unwrap();
-                println!("hdr: {:?}", &hdr);
-                if hdr.t == "#commit" {
-                    let cr: CommitRecord =
-                        serde::Deserialize::deserialize(&mut deserializer).unwrap();
-                    println!("commit: {:?}", &cr);
+        let result = firehose.next_event().await;
+        match &result {
+            Ok(_) => backoff.reset(),
+            // decoding errors only lose the one frame
+            Err(e) if e.is_decode() => {}
+            Err(Error::Closed) if !firehose.can_reconnect() => {
+                info!("end of the stream");
+                return;
+            }
+            Err(e) if !firehose.can_reconnect() => {
+                error!("{}", e);
+                return;
+            }
+            Err(e) => {
+                let delay = backoff.next_delay();
+                warn!(
+                    "{}, reconnecting from seq {:?} in {:?}",
+                    e,
+                    firehose.cursor(),
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        }
+        let read = Read {
+            result,
+            cursor: firehose.cursor(),
+            gap: firehose.last_gap(),
+            dropped: behind,
+        };
+        match tx.try_send(read) {
+            Ok(()) => behind = 0,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                if behind == 0 {
+                    warn!("processing is behind, dropping events");
                 }
+                behind += 1;
+                dropped.fetch_add(1, Ordering::Relaxed);
             }
-            x => {
-                println!("Other: {:?}", &x);
+            Err(mpsc::error::TrySendError::Closed(_)) => return,
+        }
+    }
+}
+
+/// The subscribeRepos URL of a relay given as a bare hostname, or as a URL
+/// which is used as is when it has a path
+fn relay_url(relay: &str) -> String {
+    let url = if relay.contains("://") {
+        relay.to_string()
+    } else {
+        format!("wss://{}", relay)
+    };
+    match url.split_once("://") {
+        Some((_, rest)) if rest.trim_end_matches('/').contains('/') => url,
+        _ => format!(
+            "{}/xrpc/com.atproto.sync.subscribeRepos",
+            url.trim_end_matches('/')
+        ),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum, Serialize, Deserialize)]
+enum Format {
+    /// Human readable lines
+    Text,
+    /// One JSON object per event and line, with `lag_ms`, how far behind the
+    /// clock the stream is, for the events that have a time
+    Ndjson,
+}
+
+/// The subscribe URL of a Jetstream given as a hostname or URL, asking for
+/// just the collections and repos wanted, if any
+fn jetstream_url(host: &str, collections: &[String], repos: &[String], compress: bool) -> String {
+    let url = if host.contains("://") {
+        host.to_string()
+    } else {
+        format!("wss://{}", host)
+    };
+    let mut url = match url.split_once("://") {
+        Some((_, rest)) if rest.trim_end_matches('/').contains('/') => url,
+        _ => format!("{}/subscribe", url.trim_end_matches('/')),
+    };
+    let params: Vec<String> = collections
+        .iter()
+        .map(|c| format!("wantedCollections={}", c))
+        .chain(repos.iter().map(|r| format!("wantedDids={}", r)))
+        .chain(compress.then(|| "compress=true".to_string()))
+        .collect();
+    if !params.is_empty() {
+        url.push(if url.contains('?') { '&' } else { '?' });
+        url.push_str(&params.join("&"));
+    }
+    url
+}
+
+/// This program prints the events of the firehose of an atproto relay
+#[derive(Debug, Clone, ClapParser, Serialize, Deserialize)]
+#[clap(version = "0.0.1", author = "Andrew Yourtchenko <ayourtch@gmail.com>")]
+struct Opts {
+    /// Relay to connect to, a hostname or a full URL
+    #[clap(long, default_value = "bsky.network")]
+    relay: String,
+
+    /// Connect to this Jetstream instead of a relay, e.g.
+    /// `jetstream2.us-east.bsky.network`. The cursor is then a time in microseconds
+    #[clap(long)]
+    jetstream: Option<String>,
+
+    /// Read the frames of a relay from `stdin` or `unix:<path>` instead of
+    /// connecting to it, each preceded by its length as 4 bytes big-endian
+    /// (see the `framed` module), e.g. to replay a capture in tests
+    #[clap(long, conflicts_with = "jetstream")]
+    source: Option<String>,
+
+    /// Have the Jetstream compress its messages with zstd, using the dictionary
+    /// from --zstd-dictionary
+    #[clap(long, requires = "zstd-dictionary")]
+    #[serde(default)]
+    compress: bool,
+
+    /// The zstd dictionary of Jetstream, `pkg/models/zstd_dictionary` in its repository
+    #[clap(long)]
+    zstd_dictionary: Option<String>,
+
+    /// Resume after this sequence number. Relays only keep a limited backfill window
+    #[clap(long)]
+    cursor: Option<i64>,
+
+    /// Only show commits with ops in these collections, e.g. `app.bsky.feed.post,app.bsky.feed.like`
+    #[clap(long, value_delimiter = ',')]
+    collections: Vec<String>,
+
+    /// Only show events of these repos, e.g. `did:plc:...,did:web:...`. Like
+    /// --collections, this filters after decoding, all events are still received
+    #[clap(long, value_delimiter = ',')]
+    repos: Vec<String>,
+
+    /// Keep the last seq seen in this file when stopped with Ctrl-C or by
+    /// --max-frames or --duration, and resume from it on the next start
+    /// unless --cursor is given
+    #[clap(long)]
+    state_file: Option<String>,
+
+    /// Print event counts and rates, and the average and largest lag, every
+    /// this many seconds. Unless -v is given as well, these are the only output
+    #[clap(long)]
+    stats: Option<u64>,
+
+    /// Skip the ops among the last this many seen, e.g. replayed after a
+    /// reconnect. Commits left without ops are not shown
+    #[clap(long)]
+    dedup_window: Option<usize>,
+
+    /// Stop after receiving this many events, counting those filtered out
+    #[clap(long)]
+    max_frames: Option<u64>,
+
+    /// Stop after this many seconds
+    #[clap(long)]
+    duration: Option<u64>,
+
+    /// Write the blocks of each commit shown to `<dir>/<seq>.car`
+    #[clap(long)]
+    dump_car: Option<String>,
+
+    /// Check that the block of each record created or updated hashes to the
+    /// CID of its op, logging the paths of those that don't
+    #[clap(long)]
+    #[serde(default)]
+    verify_cids: bool,
+
+    /// Print the blocks of a CAR file written by --dump-car, decoding the
+    /// records, and exit without connecting. It is a dump of the blocks, not
+    /// a replay of the commit, whose ops are not in the file
+    #[clap(long)]
+    print_car: Option<String>,
+
+    /// How to print the events
+    #[clap(long, arg_enum, default_value = "text")]
+    format: Format,
+
+    /// Delay before the first reconnect, doubling on each failure up to the max
+    #[clap(long, default_value = "1000")]
+    backoff_base_ms: u64,
+
+    /// Longest delay between reconnects
+    #[clap(long, default_value = "60000")]
+    backoff_max_ms: u64,
+
+    /// How many events can wait for their processing. Beyond that, they
+    /// are dropped rather than leaving the relay waiting, and the seq kept
+    /// in --state-file is the one before the first dropped, to replay them
+    #[clap(long, default_value = "4096")]
+    buffer: usize,
+
+    /// Reconnect when no message arrives for this long
+    #[clap(long, default_value = "60")]
+    read_timeout_secs: u64,
+
+    /// A level of verbosity, and can be used multiple times. Unless
+    /// `RUST_LOG` says otherwise, the events decoded are logged with -v and
+    /// the frames received with -vv
+    #[clap(short, long, parse(from_occurrences))]
+    verbose: i32,
+}
+
+/// Log to stderr, keeping stdout for the events, with the filter of
+/// `RUST_LOG` if set, e.g. `warn` to only see problems, and else from the
+/// level of verbosity
+fn init_logging(verbose: i32) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        let level = match verbose {
+            0 => "info",
+            1 => "debug",
+            _ => "trace",
+        };
+        // the libraries below, such as tungstenite, stay quiet
+        tracing_subscriber::EnvFilter::new(format!("info,firehose={}", level))
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+#[tokio::main]
+async fn main() {
+    let opts: Opts = Opts::parse();
+    init_logging(opts.verbose);
+    if opts.verbose > 4 {
+        let data = serde_json::to_string_pretty(&opts).unwrap();
+        println!("{}", data);
+    }
+
+    if let Some(fname) = &opts.print_car {
+        if let Err(e) = print_car(fname, opts.format) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(dir) = &opts.dump_car {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            error!("could not create {}: {}", dir, e);
+            std::process::exit(1);
+        }
+    }
+
+    let cursor = opts.cursor.or_else(|| {
+        let fname = opts.state_file.as_ref()?;
+        let data = std::fs::read_to_string(fname).ok()?;
+        match data.trim().parse() {
+            Ok(seq) => Some(seq),
+            Err(e) => {
+                warn!("{}: can't parse cursor: {}", fname, e);
+                None
+            }
+        }
+    });
+
+    let (relay, firehose) = match &opts.jetstream {
+        Some(host) => {
+            let url = jetstream_url(host, &opts.collections, &opts.repos, opts.compress);
+            let firehose = match &opts.zstd_dictionary {
+                Some(fname) => {
+                    let dictionary = match std::fs::read(fname) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            error!("could not read {}: {}", fname, e);
+                            std::process::exit(1);
+                        }
+                    };
+                    Firehose::jetstream(&url, cursor).map(|f| f.with_zstd_dictionary(dictionary))
+                }
+                None => Firehose::jetstream(&url, cursor),
+            };
+            (url, firehose)
+        }
+        None => match &opts.source {
+            Some(source) => {
+                let firehose = source
+                    .parse()
+                    .map(|source| Firehose::from_source(source, cursor));
+                (source.clone(), firehose)
+            }
+            None => {
+                let url = relay_url(&opts.relay);
+                let firehose = Firehose::new(&url, cursor);
+                (url, firehose)
             }
+        },
+    };
+    let firehose = match firehose {
+        Ok(firehose) => firehose.with_read_timeout(Duration::from_secs(opts.read_timeout_secs)),
+        Err(e) => {
+            error!("{}: {}", relay, e);
+            std::process::exit(1);
+        }
+    };
+    let backoff = Backoff::new(
+        Duration::from_millis(opts.backoff_base_ms),
+        Duration::from_millis(opts.backoff_max_ms),
+    );
+    let (tx, mut rx) = mpsc::channel(opts.buffer.max(1));
+    let dropped = Arc::new(AtomicU64::new(0));
+    tokio::spawn(read_ahead(firehose, backoff, tx, dropped.clone()));
+    let mut cursor = cursor;
+    // the cursor before the first event dropped, if any
+    let mut resume_from = None;
+    let mut stats = Stats::new();
+    let stats_period = Duration::from_secs(opts.stats.unwrap_or(0).max(1));
+    let mut stats_timer =
+        tokio::time::interval_at(tokio::time::Instant::now() + stats_period, stats_period);
+    let quiet = opts.stats.is_some() && opts.verbose == 0;
+    let mut seen_ops = opts.dedup_window.map(LruSeenOps::new);
+    let mut skewed = false;
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let deadline = opts
+        .duration
+        .map(|secs| tokio::time::Instant::now() + Duration::from_secs(secs));
+    let time_up = async move {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(time_up);
+    let mut frames = 0;
+    loop {
+        if opts.max_frames.is_some_and(|max| frames >= max) {
+            break;
+        }
+        let read = tokio::select! {
+            read = rx.recv() => match read {
+                Some(read) => read,
+                None => break,
+            },
+            _ = stats_timer.tick(), if opts.stats.is_some() => {
+                stats.flush(cursor, dropped.load(Ordering::Relaxed));
+                continue;
+            }
+            _ = &mut shutdown => break,
+            _ = &mut time_up => break,
+        };
+        // once events are dropped, the cursor stays before the first of
+        // them, for them to be replayed on resuming; as do those still
+        // buffered
+        stats.dropped += read.dropped;
+        if read.dropped > 0 && resume_from.is_none() {
+            resume_from = Some(cursor);
+        }
+        cursor = read.cursor;
+        match read.result {
+            Ok(mut event) => {
+                frames += 1;
+                if let Some(gap) = read.gap {
+                    warn!(
+                        "seq gap from {} to {}, {} events missed",
+                        gap.prev,
+                        gap.seq,
+                        gap.missed()
+                    );
+                }
+                if let Some(did) = event.did() {
+                    if !opts.repos.is_empty() && !opts.repos.iter().any(|r| r == did) {
+                        continue;
+                    }
+                }
+                if let FirehoseEvent::Commit(cr) = &event {
+                    if !opts.collections.is_empty() && !cr.touches_collections(&opts.collections) {
+                        continue;
+                    }
+                }
+                if let FirehoseEvent::CommitTooBig(commit) = &event {
+                    if !opts.collections.is_empty()
+                        && !commit.touches_collections(&opts.collections)
+                    {
+                        continue;
+                    }
+                }
+                if let FirehoseEvent::JetstreamCommit(commit) = &event {
+                    if !opts.collections.is_empty()
+                        && !opts.collections.contains(&commit.collection)
+                    {
+                        continue;
+                    }
+                }
+                if let Some(seen_ops) = &mut seen_ops {
+                    let new = match &mut event {
+                        FirehoseEvent::Commit(cr) => cr.ops.is_empty() || seen_ops.retain_new(cr),
+                        FirehoseEvent::JetstreamCommit(commit) => {
+                            seen_ops.insert((commit.did.clone(), commit.path(), commit.cid.clone()))
+                        }
+                        _ => true,
+                    };
+                    if !new {
+                        continue;
+                    }
+                }
+                if let (true, FirehoseEvent::Commit(cr)) = (opts.verify_cids, &event) {
+                    match cr.mismatched_cids() {
+                        Ok(mismatched) => {
+                            for (op, actual) in mismatched {
+                                warn!(
+                                    "{} {}: the CID is {} but the record hashes to {}",
+                                    cr.repo,
+                                    op.path,
+                                    op.cid.as_ref().map(Cid::to_string).unwrap_or_default(),
+                                    actual
+                                );
+                            }
+                        }
+                        Err(e) => warn!("{} seq {}: blocks: {}", cr.repo, cr.seq, e),
+                    }
+                }
+                if let (Some(dir), FirehoseEvent::Commit(cr)) = (&opts.dump_car, &event) {
+                    let path = std::path::Path::new(dir).join(format!("{}.car", cr.seq));
+                    if let Err(e) = std::fs::write(&path, &cr.blocks) {
+                        warn!("could not write {}: {}", path.display(), e);
+                    }
+                }
+                let now = chrono::Utc::now();
+                let age = event.age(now);
+                match age.and_then(|age| (-age).to_std().ok()) {
+                    Some(ahead) if ahead > MAX_CLOCK_SKEW => {
+                        if !skewed {
+                            warn!(
+                                "events are {:?} ahead of the clock, their lag counts as 0",
+                                ahead
+                            );
+                            skewed = true;
+                        }
+                    }
+                    _ => skewed = false,
+                }
+                let lag = event.lag(now);
+                stats.update(&event, lag);
+                if quiet {
+                    continue;
+                }
+                if opts.format == Format::Ndjson {
+                    let mut json = event.to_json();
+                    if let (Some(obj), Some(lag)) = (json.as_object_mut(), lag) {
+                        obj.insert("lag_ms".to_string(), (lag.as_millis() as u64).into());
+                    }
+                    println!("{}", json);
+                    continue;
+                }
+                match &event {
+                    FirehoseEvent::Commit(cr) => {
+                        if opts.verbose > 0 {
+                            println!("{:?}", cr);
+                        }
+                        print_commit(cr)
+                    }
+                    FirehoseEvent::CommitTooBig(commit) => {
+                        println!(
+                            "commit: {} seq {} too big, the records need to be fetched",
+                            &commit.repo, commit.seq
+                        );
+                        for path in &commit.paths {
+                            println!("{}", path);
+                        }
+                    }
+                    FirehoseEvent::Identity(ev) => println!("identity: {:?}", ev),
+                    FirehoseEvent::Account(ev) => println!("account: {:?}", ev),
+                    FirehoseEvent::Handle(ev) => println!("handle: {:?}", ev),
+                    FirehoseEvent::Tombstone(ev) => println!("tombstone: {:?}", ev),
+                    FirehoseEvent::Migrate(ev) => println!("migrate: {:?}", ev),
+                    FirehoseEvent::Info(ev) => println!("info: {:?}", ev),
+                    FirehoseEvent::JetstreamCommit(commit) => {
+                        println!("commit: {} time_us {}", &commit.did, commit.time_us);
+                        let json = commit
+                            .record
+                            .as_ref()
+                            .map(|record| record.to_string())
+                            .unwrap_or_default();
+                        println!("{} {} {}", commit.operation, commit.path(), json);
+                    }
+                    FirehoseEvent::Unknown(t) => println!("Unknown event type: {}", t),
+                }
+            }
+            // only decoding errors are passed on, for the others read_ahead reconnects
+            Err(e) => {
+                warn!("{}", e);
+                stats.decode_errors += 1;
+            }
+        }
+    }
+
+    let dropped = dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+        // those dropped last, with no event after them, are not known here
+        // but are after the cursor all the same
+        let first = resume_from.unwrap_or(cursor);
+        warn!(
+            "{} events were dropped as processing was behind, the first of them after seq {:?}",
+            dropped, first
+        );
+        cursor = first;
+    }
+    if let (Some(fname), Some(seq)) = (&opts.state_file, cursor) {
+        if let Err(e) = std::fs::write(fname, format!("{}\n", seq)) {
+            error!("could not write {}: {}", fname, e);
+            std::process::exit(1);
         }
+        info!("saved cursor {} to {}", seq, fname);
     }
 }
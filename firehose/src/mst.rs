@@ -0,0 +1,108 @@
+//! Walking of the Merkle Search Tree of a repo, which maps the paths of the
+//! records (`collection/rkey`) to their CIDs.
+//!
+//! Each node is a DAG-CBOR block with `l`, the subtree of the keys before
+//! its entries, and `e`, the entries in key order. An entry has `k`, its
+//! key without the first `p` bytes it shares with the key of the previous
+//! entry, `v`, the CID of the record, and `t`, the subtree of the keys
+//! between it and the next entry.
+
+use crate::cid::Cid;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug)]
+pub enum MstError {
+    /// A node of the tree is not among the blocks
+    MissingBlock(Cid),
+    /// A block is not a valid MST node
+    BadNode(Cid, String),
+    /// The keys of the tree are not in strictly increasing order
+    Unordered(String),
+}
+
+impl std::fmt::Display for MstError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MstError::MissingBlock(cid) => write!(f, "MST node {} is missing", cid),
+            MstError::BadNode(cid, e) => write!(f, "invalid MST node {}: {}", cid, e),
+            MstError::Unordered(key) => write!(f, "MST key {:?} is out of order", key),
+        }
+    }
+}
+
+impl std::error::Error for MstError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TreeEntry {
+    p: usize,
+    #[serde(with = "serde_bytes")]
+    k: Vec<u8>,
+    v: Cid,
+    t: Option<Cid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NodeData {
+    l: Option<Cid>,
+    e: Vec<TreeEntry>,
+}
+
+/// The records of the tree whose root node is `root`, as path and CID, in
+/// key order. All the nodes must be among the blocks, which is only the
+/// case for a full repo: the blocks of a commit event only have the nodes
+/// on the way to the records changed.
+pub fn walk_mst(root: Cid, blocks: &HashMap<Cid, Vec<u8>>) -> Result<Vec<(String, Cid)>, MstError> {
+    let mut walk = Walk {
+        blocks,
+        visited: HashSet::new(),
+        records: vec![],
+    };
+    walk.node(root)?;
+    Ok(walk.records)
+}
+
+struct Walk<'a> {
+    blocks: &'a HashMap<Cid, Vec<u8>>,
+    /// The nodes seen, as the CIDs are not checked and could make a loop
+    visited: HashSet<Cid>,
+    records: Vec<(String, Cid)>,
+}
+
+impl Walk<'_> {
+    fn node(&mut self, cid: Cid) -> Result<(), MstError> {
+        let block = self
+            .blocks
+            .get(&cid)
+            .ok_or_else(|| MstError::MissingBlock(cid.clone()))?;
+        if !self.visited.insert(cid.clone()) {
+            return Err(MstError::BadNode(
+                cid,
+                "the node is in the tree twice".to_string(),
+            ));
+        }
+        let node: NodeData = serde_cbor::from_slice(block)
+            .map_err(|e| MstError::BadNode(cid.clone(), e.to_string()))?;
+        if let Some(left) = node.l {
+            self.node(left)?;
+        }
+        let mut key: Vec<u8> = vec![];
+        for entry in node.e {
+            let prefix = key.get(..entry.p).ok_or_else(|| {
+                let e = format!("prefix of {} bytes of a {} byte key", entry.p, key.len());
+                MstError::BadNode(cid.clone(), e)
+            })?;
+            key = [prefix, &entry.k].concat();
+            let path = String::from_utf8(key.clone())
+                .map_err(|_| MstError::BadNode(cid.clone(), "a key is not UTF-8".to_string()))?;
+            if self.records.last().is_some_and(|(last, _)| *last >= path) {
+                return Err(MstError::Unordered(path));
+            }
+            self.records.push((path, entry.v));
+            if let Some(subtree) = entry.t {
+                self.node(subtree)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Verification of the signatures of repo commits.
+//!
+//! A commit is signed with the signing key of the repo, found in its DID
+//! document, which is left to the caller to resolve. The signature is over
+//! the DAG-CBOR commit object without its `sig` field, hashed with SHA-256,
+//! in the 64 byte "compact" form with a low S.
+
+use crate::cid::base58_decode;
+use k256::ecdsa::signature::Verifier;
+use serde_cbor::Value;
+
+/// Multicodec prefixes of the compressed public keys, as varints
+const SECP256K1_PREFIX: &[u8] = &[0xe7, 0x01];
+const P256_PREFIX: &[u8] = &[0x80, 0x24];
+
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The key is not a multibase secp256k1 or P-256 public key
+    BadKey(String),
+    /// The block is not a signed commit object
+    BadCommit(String),
+    /// The signature is not 64 bytes, or its S is not low
+    BadSignature,
+    /// The signature does not match the commit and the key
+    Mismatch,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::BadKey(key) => write!(f, "invalid signing key {:?}", key),
+            SignatureError::BadCommit(e) => write!(f, "invalid commit: {}", e),
+            SignatureError::BadSignature => write!(f, "invalid commit signature"),
+            SignatureError::Mismatch => write!(f, "commit signature does not match the key"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// A repo signing key, of one of the two curves atproto allows
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    K256(k256::ecdsa::VerifyingKey),
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// Parse a multibase key, like the `publicKeyMultibase` of a DID
+    /// document, e.g. `zQ3sh...`. A `did:key:` prefix is allowed.
+    pub fn from_multibase(key: &str) -> Result<PublicKey, SignatureError> {
+        let bad_key = || SignatureError::BadKey(key.to_string());
+        let bytes = key
+            .strip_prefix("did:key:")
+            .unwrap_or(key)
+            .strip_prefix('z')
+            .and_then(base58_decode)
+            .ok_or_else(bad_key)?;
+        if let Some(point) = bytes.strip_prefix(SECP256K1_PREFIX) {
+            let key = k256::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(|_| bad_key())?;
+            Ok(PublicKey::K256(key))
+        } else if let Some(point) = bytes.strip_prefix(P256_PREFIX) {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(point).map_err(|_| bad_key())?;
+            Ok(PublicKey::P256(key))
+        } else {
+            Err(bad_key())
+        }
+    }
+}
+
+impl std::str::FromStr for PublicKey {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PublicKey::from_multibase(s)
+    }
+}
+
+/// Verify the signature of a commit object, the block that the `commit`
+/// CID of a `#commit` event points to.
+pub fn verify_commit(commit_block: &[u8], signing_key: &PublicKey) -> Result<(), SignatureError> {
+    let bad_commit = |e: &dyn std::fmt::Display| SignatureError::BadCommit(e.to_string());
+    let value: Value = serde_cbor::from_slice(commit_block).map_err(|e| bad_commit(&e))?;
+    let Value::Map(mut commit) = value else {
+        return Err(bad_commit(&"not a map"));
+    };
+    let Some(Value::Bytes(sig)) = commit.remove(&Value::Text("sig".to_string())) else {
+        return Err(bad_commit(&"no signature"));
+    };
+    // the map keys of a Value are in the DAG-CBOR order, so this is the
+    // encoding that was signed
+    let unsigned = serde_cbor::to_vec(&Value::Map(commit)).map_err(|e| bad_commit(&e))?;
+    match signing_key {
+        PublicKey::K256(key) => {
+            let sig = k256::ecdsa::Signature::from_slice(&sig)
+                .map_err(|_| SignatureError::BadSignature)?;
+            if sig.normalize_s().is_some() {
+                return Err(SignatureError::BadSignature);
+            }
+            key.verify(&unsigned, &sig)
+                .map_err(|_| SignatureError::Mismatch)
+        }
+        PublicKey::P256(key) => {
+            let sig = p256::ecdsa::Signature::from_slice(&sig)
+                .map_err(|_| SignatureError::BadSignature)?;
+            if sig.normalize_s().is_some() {
+                return Err(SignatureError::BadSignature);
+            }
+            key.verify(&unsigned, &sig)
+                .map_err(|_| SignatureError::Mismatch)
+        }
+    }
+}
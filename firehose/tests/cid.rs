@@ -0,0 +1,87 @@
+//! Computing the CIDs of DAG-CBOR blocks, and checking the records of a
+//! commit against the CIDs of its ops.
+
+use firehose::car::read_car;
+use firehose::cid::DAG_CBOR;
+use firehose::{record_cid, repoOp, Cid, CommitRecord};
+
+const REPO: &[u8] = include_bytes!("fixtures/repo.car");
+
+/// The empty map, `{}`, the usual test vector of DAG-CBOR
+const EMPTY_MAP: &[u8] = &[0xa0];
+const EMPTY_MAP_CID: &str = "bafyreigbtj4x7ip5legnfznufuopl4sg4knzc2cof6duas4b3q2fy6swua";
+
+#[test]
+fn known_block() {
+    let cid = record_cid(EMPTY_MAP);
+    assert_eq!(cid.to_string(), EMPTY_MAP_CID);
+    assert_eq!(
+        (cid.version(), cid.codec(), cid.hash_code()),
+        (1, DAG_CBOR, 0x12)
+    );
+}
+
+#[test]
+fn repo_blocks() {
+    for (cid, block) in read_car(REPO).unwrap().blocks {
+        assert_eq!(record_cid(&block), cid);
+    }
+}
+
+/// A CAR file of the blocks, each under the CID given
+fn car(blocks: &[(&Cid, &[u8])]) -> Vec<u8> {
+    #[derive(serde::Serialize)]
+    struct Header {
+        version: u64,
+        roots: Vec<Cid>,
+    }
+    let header = serde_cbor::to_vec(&Header {
+        version: 1,
+        roots: vec![],
+    })
+    .unwrap();
+    let mut data = vec![header.len() as u8];
+    data.extend(header);
+    for (cid, block) in blocks {
+        data.push((cid.as_bytes().len() + block.len()) as u8);
+        data.extend(cid.as_bytes());
+        data.extend(*block);
+    }
+    data
+}
+
+#[test]
+fn mismatched_cids() {
+    let empty: Cid = EMPTY_MAP_CID.parse().unwrap();
+    // {"a": 1}, under the CID of the empty map
+    let altered: &[u8] = &[0xa1, 0x61, 0x61, 0x01];
+    let other = record_cid(&[0xa1, 0x61, 0x62, 0x02]);
+    let op = |path: &str, cid: &Cid| repoOp {
+        action: "create".to_string(),
+        path: path.to_string(),
+        cid: Some(cid.clone()),
+    };
+    let commit = CommitRecord {
+        seq: 1,
+        rebase: false,
+        tooBig: None,
+        repo: "did:plc:abc".to_string(),
+        commit: empty.clone(),
+        blocks: car(&[(&empty, altered), (&other, &[0xa1, 0x61, 0x62, 0x02])]),
+        ops: vec![
+            op("app.bsky.feed.post/3kabc22", &empty),
+            op("app.bsky.feed.post/3kabc23", &other),
+            // not among the blocks, nothing to check
+            op("app.bsky.feed.post/3kabc24", &record_cid(&[0xf6])),
+        ],
+        prev: None,
+        rev: "3kabc22".to_string(),
+        since: None,
+        blobs: vec![],
+        time: chrono::Utc::now(),
+    };
+    let mismatched = commit.mismatched_cids().unwrap();
+    assert_eq!(mismatched.len(), 1);
+    assert_eq!(mismatched[0].0.path, "app.bsky.feed.post/3kabc22");
+    assert_eq!(mismatched[0].1, record_cid(altered));
+}
@@ -0,0 +1,206 @@
+//! Decoding of frames, including those a relay should never send.
+
+use firehose::cid::DAG_CBOR;
+use firehose::{decode_frame, DecodeError, Error, FirehoseEvent, Frame, FrameType, IdentityEvent};
+
+/// A frame with the header for `t` and the body
+fn frame<T: serde::Serialize>(t: &str, body: &T) -> Vec<u8> {
+    let header = Frame {
+        op: 1,
+        t: Some(t.into()),
+    };
+    let mut data = serde_cbor::to_vec(&header).unwrap();
+    data.extend(serde_cbor::to_vec(body).unwrap());
+    data
+}
+
+fn identity() -> IdentityEvent {
+    IdentityEvent {
+        seq: 42,
+        did: "did:plc:abc".to_string(),
+        time: "2024-01-01T00:00:00.000Z".to_string(),
+        handle: Some("alice.test".to_string()),
+    }
+}
+
+/// A `#commit` frame creating a post, in a repo of that one record, encoded
+/// apart from this crate: the CIDs are those of its blocks
+const COMMIT_FRAME: &[u8] = include_bytes!("fixtures/commit.frame");
+const COMMIT_CID: &str = "bafyreibdaq4caczpdpmvdj4b2lskqruigbvixwmufrsiwruzqj3vqrtpdq";
+const RECORD_CID: &str = "bafyreida7mlcvnzbvzwdwclxmvcmog6gpx4tfah4anchu5vlbpc5n377pa";
+
+fn decode_error(data: &[u8]) -> DecodeError {
+    match decode_frame(data) {
+        Err(Error::Decode(e)) => e,
+        other => panic!("expected a decode error, got {:?}", other),
+    }
+}
+
+#[test]
+fn decode_identity() {
+    match decode_frame(&frame("#identity", &identity())).unwrap() {
+        FirehoseEvent::Identity(ev) => assert_eq!(ev.seq, 42),
+        other => panic!("expected an identity event, got {:?}", other),
+    }
+}
+
+#[test]
+fn decode_commit() {
+    let FirehoseEvent::Commit(cr) = decode_frame(COMMIT_FRAME).unwrap() else {
+        panic!("expected a commit");
+    };
+    assert_eq!(cr.seq, 1234);
+    // the tag 42 CIDs, with their multihash
+    assert_eq!(cr.commit.to_string(), COMMIT_CID);
+    assert_eq!(
+        (
+            cr.commit.version(),
+            cr.commit.codec(),
+            cr.commit.hash_code()
+        ),
+        (1, DAG_CBOR, 0x12)
+    );
+    assert_eq!(cr.commit.digest().len(), 32);
+    assert_eq!(cr.commit, COMMIT_CID.parse().unwrap());
+    let cid = cr.ops[0].cid.as_ref().unwrap();
+    assert_eq!(cid.to_string(), RECORD_CID);
+    // which are those of the blocks
+    let car = firehose::car::read_car(&cr.blocks).unwrap();
+    assert_eq!(car.roots, std::slice::from_ref(&cr.commit));
+    let (op, record) = cr.op_records().unwrap().remove(0);
+    assert_eq!(op.path, "app.bsky.feed.post/3kabc22");
+    let record = firehose::record_json(&record.unwrap()).unwrap();
+    assert_eq!(record["text"], "hello from a signed commit");
+}
+
+#[test]
+fn too_big_commit() {
+    let FirehoseEvent::Commit(mut cr) = decode_frame(COMMIT_FRAME).unwrap() else {
+        panic!("expected a commit");
+    };
+    // the blocks of a commit too big are missing, here not even a CAR
+    cr.tooBig = Some(true);
+    cr.blocks = vec![0xff; 8];
+    let FirehoseEvent::CommitTooBig(commit) = decode_frame(&frame("#commit", &cr)).unwrap() else {
+        panic!("expected a commit too big");
+    };
+    assert_eq!(
+        (commit.seq, commit.repo.as_str(), commit.rev.as_str()),
+        (1234, cr.repo.as_str(), "3kabc22")
+    );
+    assert_eq!(commit.paths, ["app.bsky.feed.post/3kabc22"]);
+    let json = FirehoseEvent::CommitTooBig(commit).to_json();
+    assert_eq!(json["tooBig"], true);
+    // without the flag, the records would be looked for in the blocks
+    cr.tooBig = None;
+    let FirehoseEvent::Commit(cr) = decode_frame(&frame("#commit", &cr)).unwrap() else {
+        panic!("expected a commit");
+    };
+    assert!(cr.op_records().is_err());
+}
+
+#[test]
+fn huge_declared_lengths() {
+    let header = serde_cbor::to_vec(&Frame {
+        op: 1,
+        t: Some(FrameType::Identity),
+    })
+    .unwrap();
+    // a map of 2^32 entries, then a byte string of 2^64 - 1 bytes
+    for body in [
+        &[0xba, 0xff, 0xff, 0xff, 0xff][..],
+        &[0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+    ] {
+        let data = [&header[..], body].concat();
+        let e = decode_error(&data);
+        assert!(
+            matches!(e, DecodeError::TooManyItems(_) | DecodeError::Truncated),
+            "{:?}",
+            e
+        );
+    }
+    // within the limits, but more items than there are bytes left
+    let data = [&header[..], &[0xb9, 0x10, 0x00, 0x01]].concat();
+    assert!(matches!(decode_error(&data), DecodeError::Truncated));
+}
+
+#[test]
+fn deep_nesting() {
+    let mut data = frame("#identity", &identity());
+    data.extend(std::iter::repeat_n(0x81, 10_000));
+    data.push(0x00);
+    assert!(matches!(decode_error(&data), DecodeError::TooDeep));
+}
+
+#[test]
+fn indefinite_lengths() {
+    let mut data = frame("#identity", &identity());
+    data.extend([0x9f, 0x00, 0xff]);
+    assert!(matches!(decode_error(&data), DecodeError::BadItem(0x9f)));
+}
+
+#[test]
+fn unexpected_body() {
+    let data = frame("#identity", &vec![1, 2, 3]);
+    assert!(matches!(decode_error(&data), DecodeError::Cbor(_)));
+}
+
+#[test]
+fn frame_types() {
+    assert_eq!(FrameType::from("#commit"), FrameType::Commit);
+    assert_eq!(FrameType::from("commit"), FrameType::Commit);
+    assert_eq!(
+        FrameType::from("#sync"),
+        FrameType::Unknown("#sync".to_string())
+    );
+    match decode_frame(&frame("#sync", &identity())).unwrap() {
+        FirehoseEvent::Unknown(t) => assert_eq!(t, "#sync"),
+        other => panic!("expected an unknown event, got {:?}", other),
+    }
+}
+
+#[test]
+fn event_lag() {
+    use chrono::{TimeDelta, Utc};
+    use std::time::Duration;
+
+    let now = Utc::now();
+    let commit = |time| {
+        FirehoseEvent::CommitTooBig(firehose::TooBigCommit {
+            seq: 1,
+            repo: "did:plc:abc".to_string(),
+            rev: "3kabc22".to_string(),
+            time,
+            paths: vec![],
+        })
+    };
+    let behind = commit(now - TimeDelta::milliseconds(1500));
+    assert_eq!(behind.lag(now), Some(Duration::from_millis(1500)));
+    // the clocks are off, the event is not behind
+    let ahead = commit(now + TimeDelta::seconds(2));
+    assert_eq!(ahead.age(now), Some(TimeDelta::seconds(-2)));
+    assert_eq!(ahead.lag(now), Some(Duration::ZERO));
+    assert_eq!(FirehoseEvent::Unknown("#foo".to_string()).lag(now), None);
+}
+
+#[test]
+fn event_time() {
+    use chrono::{DateTime, Utc};
+    use firehose::jetstream::decode_jetstream;
+
+    let time: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+    assert_eq!(FirehoseEvent::Identity(identity()).event_time(), Some(time));
+    let mut bad = identity();
+    bad.time = "yesterday".to_string();
+    assert_eq!(FirehoseEvent::Identity(bad).event_time(), None);
+
+    // the time of a Jetstream commit is its time_us, in the JSON as well
+    let message = r#"{"did":"did:plc:abc","time_us":1704067200500000,"kind":"commit",
+        "commit":{"rev":"3kabc22","operation":"delete","collection":"app.bsky.feed.post","rkey":"3kabc22"}}"#;
+    let (event, cursor) = decode_jetstream(message).unwrap();
+    assert_eq!(cursor, 1704067200500000);
+    let time_us = time + chrono::TimeDelta::milliseconds(500);
+    assert_eq!(event.event_time(), Some(time_us));
+    assert_eq!(event.to_json()["time"], "2024-01-01T00:00:00.500Z");
+    assert_eq!(event.lag(time_us), Some(std::time::Duration::ZERO));
+}
@@ -0,0 +1,90 @@
+//! Reading the frames of a relay from a stream rather than a websocket.
+
+use firehose::framed::{read_frame, write_frame};
+use firehose::{Error, Firehose, FirehoseEvent, Frame, IdentityEvent, Source};
+use std::path::{Path, PathBuf};
+
+/// An identity frame of the seq
+fn frame(seq: i64) -> Vec<u8> {
+    let header = Frame {
+        op: 1,
+        t: Some("#identity".into()),
+    };
+    let body = IdentityEvent {
+        seq,
+        did: "did:plc:abc".to_string(),
+        time: "2024-01-01T00:00:00.000Z".to_string(),
+        handle: None,
+    };
+    let mut data = serde_cbor::to_vec(&header).unwrap();
+    data.extend(serde_cbor::to_vec(&body).unwrap());
+    data
+}
+
+fn stream(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = vec![];
+    for frame in frames {
+        write_frame(&mut data, frame).unwrap();
+    }
+    data
+}
+
+#[tokio::test]
+async fn read_frames() {
+    let data = stream(&[frame(1), vec![], frame(2)]);
+    let mut reader = &data[..];
+    assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), Some(frame(1)));
+    assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), Some(vec![]));
+    assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), Some(frame(2)));
+    assert_eq!(read_frame(&mut reader, 1024).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn bad_frames() {
+    let data = stream(&[frame(1)]);
+    // cut within the length, then within the message
+    for len in [2, data.len() - 1] {
+        let e = read_frame(&mut &data[..len], 1024).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+    let e = read_frame(&mut &data[..], 4).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn parse_source() {
+    assert_eq!("stdin".parse::<Source>().unwrap(), Source::Stdin);
+    assert_eq!(
+        "unix:/tmp/relay.sock".parse::<Source>().unwrap(),
+        Source::Unix(PathBuf::from("/tmp/relay.sock"))
+    );
+    assert!(matches!(
+        "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos".parse(),
+        Ok(Source::WebSocket(_))
+    ));
+    assert!(matches!("nope".parse::<Source>(), Err(Error::Url(_))));
+}
+
+#[tokio::test]
+async fn unix_socket() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("framed.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    let server = tokio::spawn(async move {
+        use tokio::io::AsyncWriteExt;
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let data = stream(&[frame(1), frame(2)]);
+        socket.write_all(&data).await.unwrap();
+    });
+    let mut firehose = Firehose::from_source(Source::Unix(path), None);
+    assert!(firehose.subscribe_url().is_none());
+    for seq in [1, 2] {
+        match firehose.next_event().await.unwrap() {
+            FirehoseEvent::Identity(ev) => assert_eq!(ev.seq, seq),
+            other => panic!("expected an identity event, got {:?}", other),
+        }
+    }
+    assert!(matches!(firehose.next_event().await, Err(Error::Closed)));
+    assert_eq!(firehose.cursor(), Some(2));
+    server.await.unwrap();
+}
@@ -0,0 +1,63 @@
+//! Walking the MST of a small repo, `fixtures/repo.car`, in the format of
+//! `com.atproto.sync.getRepo`: a commit whose tree has a root node with one
+//! entry, with a subtree on either side of it, and five records.
+
+use firehose::car::read_car;
+use firehose::mst::{walk_mst, MstError};
+use firehose::Cid;
+use std::collections::HashMap;
+
+const REPO: &[u8] = include_bytes!("fixtures/repo.car");
+
+/// The blocks of the repo, and the root of its tree
+fn repo() -> (HashMap<Cid, Vec<u8>>, Cid) {
+    let car = read_car(REPO).unwrap();
+    let blocks: HashMap<Cid, Vec<u8>> = car.blocks.into_iter().collect();
+    let commit = serde_cbor::from_slice::<serde_cbor::Value>(&blocks[&car.roots[0]]).unwrap();
+    let serde_cbor::Value::Map(commit) = commit else {
+        panic!("the commit is not a map");
+    };
+    let data = commit[&serde_cbor::Value::Text("data".to_string())].clone();
+    let data: Cid = serde_cbor::value::from_value(data).unwrap();
+    (blocks, data)
+}
+
+#[test]
+fn walk_repo() {
+    let (blocks, root) = repo();
+    let records = walk_mst(root, &blocks).unwrap();
+    let paths: Vec<&str> = records.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "app.bsky.feed.like/3kzzz2",
+            "app.bsky.feed.post/3kabc22",
+            "app.bsky.feed.post/3kabc23",
+            "app.bsky.feed.post/3kabc26",
+            "app.bsky.feed.post/3kabc27",
+        ]
+    );
+    for (path, cid) in &records {
+        let record = firehose::record_json(&blocks[cid]).unwrap();
+        let collection = path.split('/').next().unwrap();
+        assert_eq!(record["$type"], collection);
+    }
+}
+
+#[test]
+fn missing_subtree() {
+    let (mut blocks, root) = repo();
+    let records = walk_mst(root.clone(), &blocks).unwrap();
+    // what is neither a record, the root node nor the commit is a subtree
+    let commit = read_car(REPO).unwrap().roots[0].clone();
+    let subtree = blocks
+        .keys()
+        .find(|cid| **cid != root && **cid != commit && !records.iter().any(|(_, r)| r == *cid))
+        .cloned()
+        .unwrap();
+    blocks.remove(&subtree);
+    match walk_mst(root, &blocks) {
+        Err(MstError::MissingBlock(cid)) => assert_eq!(cid, subtree),
+        other => panic!("expected a missing block, got {:?}", other),
+    }
+}
@@ -0,0 +1,67 @@
+//! Verifying the signature of `fixtures/signed_commit.cbor`, a v3 commit
+//! object of a repo with one record, signed with the secp256k1 key `KEY`.
+//! It was encoded and signed apart from this crate, so that the encoding
+//! checked against is not the one of `verify_commit` itself.
+
+use firehose::signature::{verify_commit, PublicKey, SignatureError};
+use serde_cbor::Value;
+
+const COMMIT: &[u8] = include_bytes!("fixtures/signed_commit.cbor");
+const KEY: &str = "did:key:zQ3shpJiNywPDZUR8SSZU3disiUNXdAe1zZUHJr9jiQ7qcZef";
+
+fn key() -> PublicKey {
+    KEY.parse().unwrap()
+}
+
+/// The commit with its signature replaced
+fn with_sig(sig: Vec<u8>) -> Vec<u8> {
+    let Value::Map(mut commit) = serde_cbor::from_slice(COMMIT).unwrap() else {
+        panic!("the commit is not a map");
+    };
+    commit.insert(Value::Text("sig".to_string()), Value::Bytes(sig));
+    serde_cbor::to_vec(&Value::Map(commit)).unwrap()
+}
+
+#[test]
+fn signed_commit() {
+    verify_commit(COMMIT, &key()).unwrap();
+    // the same signature, put back, is still the one of the commit
+    let Value::Map(commit) = serde_cbor::from_slice(COMMIT).unwrap() else {
+        panic!("the commit is not a map");
+    };
+    let Some(Value::Bytes(sig)) = commit.get(&Value::Text("sig".to_string())) else {
+        panic!("the commit has no signature");
+    };
+    assert_eq!(with_sig(sig.clone()), COMMIT);
+}
+
+#[test]
+fn changed_commit() {
+    let rev = COMMIT.windows(7).position(|w| w == b"3kabc22").unwrap();
+    let mut changed = COMMIT.to_vec();
+    changed[rev + 6] = b'3';
+    assert!(matches!(
+        verify_commit(&changed, &key()),
+        Err(SignatureError::Mismatch)
+    ));
+}
+
+#[test]
+fn high_s_signature() {
+    let Value::Map(commit) = serde_cbor::from_slice(COMMIT).unwrap() else {
+        panic!("the commit is not a map");
+    };
+    let Some(Value::Bytes(sig)) = commit.get(&Value::Text("sig".to_string())) else {
+        panic!("the commit has no signature");
+    };
+    // (r, n - s) is as valid as (r, s) to ECDSA, but atproto only allows a
+    // low S, so that each commit has a single signature
+    let sig = k256::ecdsa::Signature::from_slice(sig).unwrap();
+    let (r, s) = sig.split_scalars();
+    let high = k256::ecdsa::Signature::from_scalars(r.to_bytes(), (-*s).to_bytes()).unwrap();
+    assert_eq!(high.normalize_s(), Some(sig));
+    assert!(matches!(
+        verify_commit(&with_sig(high.to_vec()), &key()),
+        Err(SignatureError::BadSignature)
+    ));
+}